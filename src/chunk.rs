@@ -1,7 +1,10 @@
 mod disassemble;
 
+use std::rc::Rc;
+
 use crate::{
-    instructions::{Op, Opcode},
+    instructions::{Op, Opcode, UpvalueRef, LONG_CONST_MAX},
+    scanner::Span,
     value::Value,
 };
 
@@ -13,6 +16,10 @@ pub struct Chunk {
     constants: Vec<Value>,
     // Public because runtime_error reads this to report the line
     pub lines: Vec<usize>,
+    // The source span each byte was compiled from, so errors can point at the exact expression
+    pub spans: Vec<Span>,
+    // The source the spans point into - kept here since a function can outlive the REPL line that defined it
+    pub source: Rc<str>,
 }
 
 impl Default for Chunk {
@@ -26,24 +33,32 @@ impl Chunk {
             code: vec![],
             constants: vec![],
             lines: vec![],
+            spans: vec![],
+            source: Rc::from(""),
         }
     }
-    pub fn write(&mut self, ins: Op, line: usize) {
+    pub fn write(&mut self, ins: Op, line: usize, span: Span) {
         macro_rules! simple_op {
             ($kind: ident) => {
-                self.write_code(Opcode::$kind.into(), line)
+                self.write_code(Opcode::$kind.into(), line, span)
             };
         }
         macro_rules! double_op {
             ($kind: ident, $val: ident) => {{
                 simple_op!($kind);
-                self.write_code($val, line)
+                self.write_code($val, line, span)
+            }};
+        }
+        macro_rules! u24_op {
+            ($kind: ident, $val: ident) => {{
+                simple_op!($kind);
+                self.write_u24($val, line, span)
             }};
         }
         macro_rules! u16_op {
             ($kind: ident, $val: ident) => {{
                 simple_op!($kind);
-                self.write_u16($val, line)
+                self.write_u16($val, line, span)
             }};
         }
         match ins {
@@ -51,16 +66,48 @@ impl Chunk {
             Op::DefineGlobal(val) => double_op!(DefineGlobal, val),
             Op::GetGlobal(val) => double_op!(GetGlobal, val),
             Op::SetGlobal(val) => double_op!(SetGlobal, val),
+            Op::ConstantLong(val) => u24_op!(ConstantLong, val),
+            Op::DefineGlobalLong(val) => u24_op!(DefineGlobalLong, val),
+            Op::GetGlobalLong(val) => u24_op!(GetGlobalLong, val),
+            Op::SetGlobalLong(val) => u24_op!(SetGlobalLong, val),
+            Op::GetPropertyLong(val) => u24_op!(GetPropertyLong, val),
+            Op::SetPropertyLong(val) => u24_op!(SetPropertyLong, val),
+            Op::ClassLong(val) => u24_op!(ClassLong, val),
+            Op::MethodLong(val) => u24_op!(MethodLong, val),
+            Op::GetSuperLong(val) => u24_op!(GetSuperLong, val),
             Op::GetLocal(val) => double_op!(GetLocal, val),
             Op::SetLocal(val) => double_op!(SetLocal, val),
+            Op::Call(val) => double_op!(Call, val),
+            Op::GetUpvalue(val) => double_op!(GetUpvalue, val),
+            Op::SetUpvalue(val) => double_op!(SetUpvalue, val),
+            Op::GetProperty(val) => double_op!(GetProperty, val),
+            Op::SetProperty(val) => double_op!(SetProperty, val),
+            Op::BuildList(val) => double_op!(BuildList, val),
+            Op::BuildMap(val) => double_op!(BuildMap, val),
+            Op::GetIndex => simple_op!(GetIndex),
+            Op::SetIndex => simple_op!(SetIndex),
+            Op::Class(val) => double_op!(Class, val),
+            Op::Method(val) => double_op!(Method, val),
+            Op::GetSuper(val) => double_op!(GetSuper, val),
+            Op::Closure(val, upvalues) => {
+                double_op!(Closure, val);
+                self.write_upvalues(upvalues, line, span);
+            }
+            Op::ClosureLong(val, upvalues) => {
+                u24_op!(ClosureLong, val);
+                self.write_upvalues(upvalues, line, span);
+            }
 
             Op::Jump(val) => u16_op!(Jump, val),
             Op::JumpIfFalse(val) => u16_op!(JumpIfFalse, val),
             Op::Loop(val) => u16_op!(Loop, val),
 
             Op::Return => simple_op!(Return),
+            Op::CloseUpvalue => simple_op!(CloseUpvalue),
+            Op::Inherit => simple_op!(Inherit),
             Op::Print => simple_op!(Print),
             Op::Pop => simple_op!(Pop),
+            Op::Stringify => simple_op!(Stringify),
             Op::Negate => simple_op!(Negate),
             Op::Not => simple_op!(Not),
             Op::Equal => simple_op!(Equal),
@@ -69,29 +116,55 @@ impl Chunk {
             Op::Add => simple_op!(Add),
             Op::Subtract => simple_op!(Subtract),
             Op::Multiply => simple_op!(Multiply),
+            Op::Modulo => simple_op!(Modulo),
+            Op::Power => simple_op!(Power),
+            Op::FloorDivide => simple_op!(FloorDivide),
+            Op::BitAnd => simple_op!(BitAnd),
+            Op::BitOr => simple_op!(BitOr),
+            Op::BitXor => simple_op!(BitXor),
+            Op::BitNot => simple_op!(BitNot),
+            Op::ShiftLeft => simple_op!(ShiftLeft),
+            Op::ShiftRight => simple_op!(ShiftRight),
             Op::Divide => simple_op!(Divide),
             Op::True => simple_op!(True),
             Op::False => simple_op!(False),
             Op::Nil => simple_op!(Nil),
         }
     }
-    fn write_code(&mut self, code: u8, line: usize) {
+    fn write_code(&mut self, code: u8, line: usize, span: Span) {
         self.code.push(code);
         self.lines.push(line);
+        self.spans.push(span);
     }
-    fn write_u16(&mut self, value: u16, line: usize) {
+    fn write_u16(&mut self, value: u16, line: usize, span: Span) {
         // Writes the u16 as two separate u8s (big endian order)
         for byte in value.to_be_bytes() {
-            self.write_code(byte, line);
+            self.write_code(byte, line, span);
         }
     }
-    pub fn add_constant(&mut self, value: Value) -> Option<u8> {
+    fn write_u24(&mut self, value: u32, line: usize, span: Span) {
+        // Same as write_u16, but skipping the most significant byte
+        for byte in &value.to_be_bytes()[1..] {
+            self.write_code(*byte, line, span);
+        }
+    }
+    // Closures are variable length - followed by a pair of bytes for each upvalue the closure captures
+    fn write_upvalues(&mut self, upvalues: Vec<UpvalueRef>, line: usize, span: Span) {
+        for upvalue in upvalues {
+            self.write_code(upvalue.is_local.into(), line, span);
+            self.write_code(upvalue.index, line, span);
+        }
+    }
+    pub fn add_constant(&mut self, value: Value) -> Option<usize> {
+        if self.constants.len() == LONG_CONST_MAX {
+            return None;
+        }
         self.constants.push(value);
-        (self.constants.len() - 1).try_into().ok()
+        Some(self.constants.len() - 1)
     }
-    pub fn get_constant_unwrap(&self, const_idx: u8) -> &Value {
+    pub fn get_constant_unwrap(&self, const_idx: usize) -> &Value {
         self.constants
-            .get(const_idx as usize)
+            .get(const_idx)
             .unwrap_or_else(|| panic!("Invalid constant index {const_idx}"))
     }
 }
@@ -1,15 +1,24 @@
-use crate::{chunk::Chunk, instructions::Opcode};
+use std::io::Write;
+
+use crate::{chunk::Chunk, instructions::Opcode, value::Value};
+
+// This is only debug output, so a failed write isn't worth stopping for
+macro_rules! out {
+    ($out: expr, $($arg: tt)*) => {{
+        let _ = write!($out, $($arg)*);
+    }};
+}
 
 impl Chunk {
-    pub fn disassemble(&self, name: &str) {
-        println!("== {name} ==");
+    pub fn disassemble(&self, name: &str, out: &mut dyn Write) {
+        out!(out, "== {name} ==\n");
 
         let mut offset = 0;
         while offset < self.code.len() {
-            offset = self.disassemble_instruction(offset);
+            offset = self.disassemble_instruction(offset, out);
         }
     }
-    pub fn disassemble_instruction(&self, mut offset: usize) -> usize {
+    pub fn disassemble_instruction(&self, mut offset: usize, out: &mut dyn Write) -> usize {
         let read_line = |o| {
             *self
                 .lines
@@ -25,65 +34,153 @@ impl Chunk {
             v
         };
 
-        print!("{:04} ", offset);
+        out!(out, "{:04} ", offset);
         let line = read_line(offset);
         if offset == 0 || line != read_line(offset - 1) {
-            print!("{:04} ", line);
+            out!(out, "{:04} ", line);
         } else {
-            print!("   | ")
+            out!(out, "   | ")
         }
         macro_rules! op_with_const_idx {
             ($op_code: literal) => {{
                 let const_idx = read_byte(&mut offset);
-                let val = self.get_constant_unwrap(const_idx);
-                print!("{:16} {const_idx:4} '{val}'", $op_code);
+                let val = self.get_constant_unwrap(const_idx as usize);
+                out!(out, "{:16} {const_idx:4} '{val}'", $op_code);
+            }};
+        }
+        macro_rules! op_with_long_const_idx {
+            ($op_code: literal) => {{
+                let const_idx = u32::from_be_bytes([
+                    0,
+                    read_byte(&mut offset),
+                    read_byte(&mut offset),
+                    read_byte(&mut offset),
+                ]);
+                let val = self.get_constant_unwrap(const_idx as usize);
+                out!(out, "{:16} {const_idx:4} '{val}'", $op_code);
+            }};
+        }
+        // Prints the captures that follow a closure instruction, for the function in the given constant
+        macro_rules! closure_upvalues {
+            ($const_idx: expr) => {{
+                let Value::Function(function) = self.get_constant_unwrap($const_idx as usize)
+                else {
+                    panic!("OP_CLOSURE constant should be a function")
+                };
+                for _ in 0..function.upvalue_count {
+                    let upvalue_offset = offset;
+                    let is_local = read_byte(&mut offset) == 1;
+                    let index = read_byte(&mut offset);
+                    out!(
+                        out,
+                        "\n{upvalue_offset:04}    |                     {} {index}",
+                        if is_local { "local" } else { "upvalue" }
+                    );
+                }
             }};
         }
         macro_rules! op_with_byte_arg {
             ($op_code: literal) => {{
                 let byte = read_byte(&mut offset);
-                print!("{:16} {byte:4}", $op_code);
+                out!(out, "{:16} {byte:4}", $op_code);
+            }};
+        }
+        macro_rules! op_with_u24_arg {
+            ($op_code: literal) => {{
+                let val = u32::from_be_bytes([
+                    0,
+                    read_byte(&mut offset),
+                    read_byte(&mut offset),
+                    read_byte(&mut offset),
+                ]);
+                out!(out, "{:16} {val:4}", $op_code);
             }};
         }
         macro_rules! op_with_u16_arg {
             ($op_code: literal) => {{
                 let val = u16::from_be_bytes([read_byte(&mut offset), read_byte(&mut offset)]);
 
-                print!("{:16} {val:4}", $op_code);
+                out!(out, "{:16} {val:4}", $op_code);
             }};
         }
         match read_byte(&mut offset).try_into() {
             // double match saves `Ok()` wrapping on all the cases
             Ok(op) => match op {
-                Opcode::Return => print!("OP_RETURN"),
+                Opcode::Return => out!(out, "OP_RETURN"),
                 Opcode::Jump => op_with_u16_arg!("OP_JUMP"),
                 Opcode::JumpIfFalse => op_with_u16_arg!("OP_JUMP_IF_FALSE"),
                 Opcode::Loop => op_with_u16_arg!("OP_LOOP"),
                 Opcode::Constant => op_with_const_idx!("OP_CONSTANT"),
-                Opcode::DefineGlobal => op_with_const_idx!("OP_DEFINE_GLOBAL"),
-                Opcode::GetGlobal => op_with_const_idx!("OP_GET_GLOBAL"),
-                Opcode::SetGlobal => op_with_const_idx!("OP_SET_GLOBAL"),
+                Opcode::DefineGlobal => op_with_byte_arg!("OP_DEFINE_GLOBAL"),
+                Opcode::GetGlobal => op_with_byte_arg!("OP_GET_GLOBAL"),
+                Opcode::SetGlobal => op_with_byte_arg!("OP_SET_GLOBAL"),
+                Opcode::ConstantLong => op_with_long_const_idx!("OP_CONSTANT_LONG"),
+                Opcode::DefineGlobalLong => op_with_u24_arg!("OP_DEFINE_GLOBAL_LONG"),
+                Opcode::GetGlobalLong => op_with_u24_arg!("OP_GET_GLOBAL_LONG"),
+                Opcode::SetGlobalLong => op_with_u24_arg!("OP_SET_GLOBAL_LONG"),
+                Opcode::GetPropertyLong => op_with_long_const_idx!("OP_GET_PROPERTY_LONG"),
+                Opcode::SetPropertyLong => op_with_long_const_idx!("OP_SET_PROPERTY_LONG"),
+                Opcode::ClassLong => op_with_long_const_idx!("OP_CLASS_LONG"),
+                Opcode::MethodLong => op_with_long_const_idx!("OP_METHOD_LONG"),
+                Opcode::GetSuperLong => op_with_long_const_idx!("OP_GET_SUPER_LONG"),
                 Opcode::GetLocal => op_with_byte_arg!("OP_GET_LOCAL"),
                 Opcode::SetLocal => op_with_byte_arg!("OP_SET_LOCAL"),
-                Opcode::Print => print!("OP_PRINT"),
-                Opcode::Pop => print!("OP_POP"),
-                Opcode::Negate => print!("OP_NEGATE"),
-                Opcode::Not => print!("OP_NOT"),
-                Opcode::Equal => print!("OP_EQUAL"),
-                Opcode::Greater => print!("OP_GREATER"),
-                Opcode::Less => print!("OP_LESS"),
-                Opcode::Add => print!("OP_ADD"),
-                Opcode::Subtract => print!("OP_SUBTRACT"),
-                Opcode::Multiply => print!("OP_MULTIPLY"),
-                Opcode::Divide => print!("OP_DIVIDE"),
-                Opcode::True => print!("OP_TRUE"),
-                Opcode::False => print!("OP_FALSE"),
-                Opcode::Nil => print!("OP_NIL"),
+                Opcode::Call => op_with_byte_arg!("OP_CALL"),
+                Opcode::GetUpvalue => op_with_byte_arg!("OP_GET_UPVALUE"),
+                Opcode::SetUpvalue => op_with_byte_arg!("OP_SET_UPVALUE"),
+                Opcode::GetProperty => op_with_const_idx!("OP_GET_PROPERTY"),
+                Opcode::SetProperty => op_with_const_idx!("OP_SET_PROPERTY"),
+                Opcode::BuildList => op_with_byte_arg!("OP_BUILD_LIST"),
+                Opcode::BuildMap => op_with_byte_arg!("OP_BUILD_MAP"),
+                Opcode::GetIndex => out!(out, "OP_GET_INDEX"),
+                Opcode::SetIndex => out!(out, "OP_SET_INDEX"),
+                Opcode::Class => op_with_const_idx!("OP_CLASS"),
+                Opcode::Method => op_with_const_idx!("OP_METHOD"),
+                Opcode::Inherit => out!(out, "OP_INHERIT"),
+                Opcode::GetSuper => op_with_const_idx!("OP_GET_SUPER"),
+                Opcode::CloseUpvalue => out!(out, "OP_CLOSE_UPVALUE"),
+                Opcode::Closure => {
+                    op_with_const_idx!("OP_CLOSURE");
+                    closure_upvalues!(self.code[offset - 1]);
+                }
+                Opcode::ClosureLong => {
+                    op_with_long_const_idx!("OP_CLOSURE_LONG");
+                    closure_upvalues!(u32::from_be_bytes([
+                        0,
+                        self.code[offset - 3],
+                        self.code[offset - 2],
+                        self.code[offset - 1]
+                    ]));
+                }
+                Opcode::Print => out!(out, "OP_PRINT"),
+                Opcode::Pop => out!(out, "OP_POP"),
+                Opcode::Stringify => out!(out, "OP_STRINGIFY"),
+                Opcode::Negate => out!(out, "OP_NEGATE"),
+                Opcode::Not => out!(out, "OP_NOT"),
+                Opcode::Equal => out!(out, "OP_EQUAL"),
+                Opcode::Greater => out!(out, "OP_GREATER"),
+                Opcode::Less => out!(out, "OP_LESS"),
+                Opcode::Add => out!(out, "OP_ADD"),
+                Opcode::Subtract => out!(out, "OP_SUBTRACT"),
+                Opcode::Multiply => out!(out, "OP_MULTIPLY"),
+                Opcode::Modulo => out!(out, "OP_MODULO"),
+                Opcode::Power => out!(out, "OP_POWER"),
+                Opcode::FloorDivide => out!(out, "OP_FLOOR_DIVIDE"),
+                Opcode::BitAnd => out!(out, "OP_BIT_AND"),
+                Opcode::BitOr => out!(out, "OP_BIT_OR"),
+                Opcode::BitXor => out!(out, "OP_BIT_XOR"),
+                Opcode::BitNot => out!(out, "OP_BIT_NOT"),
+                Opcode::ShiftLeft => out!(out, "OP_SHIFT_LEFT"),
+                Opcode::ShiftRight => out!(out, "OP_SHIFT_RIGHT"),
+                Opcode::Divide => out!(out, "OP_DIVIDE"),
+                Opcode::True => out!(out, "OP_TRUE"),
+                Opcode::False => out!(out, "OP_FALSE"),
+                Opcode::Nil => out!(out, "OP_NIL"),
             },
 
-            Err(ins) => print!("Unknown opcode {ins}"),
+            Err(ins) => out!(out, "Unknown opcode {ins}"),
         }
-        println!();
+        out!(out, "\n");
         offset
     }
 }
@@ -1,41 +1,68 @@
+use std::io::Write;
+
 use crate::chunk::Chunk;
-use crate::instructions::Op;
-use crate::scanner::{Scanner, Token, TokenKind};
-use crate::value::{StringInterns, Value};
+use crate::diagnostic::{Diagnostic, DiagnosticKind};
+use crate::globals::Globals;
+use crate::instructions::{Op, UpvalueRef, GLOBAL_SLOT_MAX};
+use crate::scanner::{Scanner, Span, Token, TokenKind};
+use crate::value::{Function, StringInterns, Value};
 
 mod compiler_state;
-use compiler_state::Compiler;
+use compiler_state::{Compiler, FunctionKind, LoopContext};
 
 mod parser;
 
-pub fn compile(str: String, strings: &mut StringInterns) -> Option<Chunk> {
-    let mut parser = Parser::new(Scanner::new(str), strings);
+pub fn compile(
+    str: String,
+    strings: &mut StringInterns,
+    globals: &mut Globals,
+    debug_output: &mut dyn Write,
+) -> Result<Function, Vec<Diagnostic>> {
+    let mut parser = Parser::new(Scanner::new(str), strings, globals, debug_output);
 
     while !parser.match_t(TokenKind::Eof) {
         parser.declaration();
     }
 
-    // end_compiler functionality
-    parser.emit_ins(Op::Return);
+    let (function, _) = parser.end_compiler();
 
-    if parser.had_error {
-        return None;
-    }
-    if cfg!(feature = "DEBUG_PRINT_CODE") {
-        parser.chunk.disassemble("code");
+    if !parser.diagnostics.is_empty() {
+        return Err(parser.diagnostics);
     }
-    Some(parser.chunk)
+    Ok(function)
 }
 
 struct Parser<'a> {
     scanner: Scanner,
-    chunk: Chunk,
     previous: Option<Token>,
     current: Token,
-    had_error: bool,
+    // The token after current, when something has needed to look that far ahead
+    next: Option<Token>,
+    // Every error found, in the order they were found - only the first after each synchronize is kept
+    diagnostics: Vec<Diagnostic>,
     panic_mode: bool,
     strings: &'a mut StringInterns,
+    // Global slots are shared with the VM, and with any earlier compiles in the same VM
+    globals: &'a mut Globals,
+    // Where DEBUG_PRINT_CODE writes the finished functions
+    debug_output: &'a mut dyn Write,
     compiler: Compiler,
+    // The class bodies we're nested in, innermost last - used to check `this` and `super` are valid
+    class_compilers: Vec<ClassCompiler>,
+}
+
+struct ClassCompiler {
+    has_superclass: bool,
+}
+
+// A token for a name that doesn't appear in the source, like the hidden `super` local
+fn synthetic_token(lexeme: &str, line: usize) -> Token {
+    Token {
+        kind: TokenKind::Identifier,
+        lexeme: lexeme.to_string(),
+        line,
+        span: Span::default(),
+    }
 }
 
 fn stub_token() -> Token {
@@ -43,22 +70,31 @@ fn stub_token() -> Token {
         kind: TokenKind::Eof,
         lexeme: "".to_string(),
         line: 0,
+        span: Span::default(),
     }
 }
 
 // The basic parser operations - advance, consume, etc
 impl<'a> Parser<'a> {
-    fn new(scanner: Scanner, strings: &'a mut StringInterns) -> Parser<'a> {
+    fn new(
+        scanner: Scanner,
+        strings: &'a mut StringInterns,
+        globals: &'a mut Globals,
+        debug_output: &'a mut dyn Write,
+    ) -> Parser<'a> {
         let mut p = Parser {
             scanner,
-            chunk: Chunk::new(),
             previous: None,
             // We immediately advance the parser which will override this anyway - not worth making this an Option
             current: stub_token(),
-            had_error: false,
+            next: None,
+            diagnostics: vec![],
             panic_mode: false,
             strings,
-            compiler: Compiler::new(),
+            globals,
+            debug_output,
+            compiler: Compiler::new(FunctionKind::Script, None),
+            class_compilers: vec![],
         };
         p.advance();
         p
@@ -67,17 +103,29 @@ impl<'a> Parser<'a> {
         self.previous.as_ref().unwrap()
     }
     fn advance(&mut self) {
-        let new_token = loop {
+        let new_token = match self.next.take() {
+            Some(token) => token,
+            None => self.scan_token(),
+        };
+        self.previous = Some(std::mem::replace(&mut self.current, new_token));
+    }
+    // Reports and skips over any scan errors, so the parser only ever sees valid tokens
+    fn scan_token(&mut self) -> Token {
+        loop {
             match self.scanner.scan_token() {
-                Ok(token) => {
-                    break token;
-                }
+                Ok(token) => return token,
                 Err(err) => {
-                    self.print_err(&err.msg, err.line, None);
+                    let diagnostic = Diagnostic::new(
+                        DiagnosticKind::CompileError,
+                        &err.msg,
+                        err.span.line,
+                        err.span,
+                        self.scanner.source(),
+                    );
+                    self.report(diagnostic);
                 }
             }
-        };
-        self.previous = Some(std::mem::replace(&mut self.current, new_token));
+        }
     }
     fn consume(&mut self, expected: TokenKind, err: &str) {
         if self.current.kind == expected {
@@ -89,6 +137,15 @@ impl<'a> Parser<'a> {
     fn check(&self, expected: TokenKind) -> bool {
         self.current.kind == expected
     }
+    // Like check, but for the token after the current one
+    fn check_next(&mut self, expected: TokenKind) -> bool {
+        if self.next.is_none() {
+            self.next = Some(self.scan_token());
+        }
+        self.next
+            .as_ref()
+            .is_some_and(|token| token.kind == expected)
+    }
     fn match_t(&mut self, expected: TokenKind) -> bool {
         if self.check(expected) {
             self.advance();
@@ -99,35 +156,48 @@ impl<'a> Parser<'a> {
 
     // Top-level error methods
     fn error_at_current(&mut self, err: &str) {
-        self.error_at_token(err, |s| &s.current);
+        self.error_at_token(err, None, |s| &s.current);
     }
     fn error(&mut self, err: &str) {
-        self.error_at_token(err, |s| s.assert_prev());
+        self.error_at_token(err, None, |s| s.assert_prev());
+    }
+    // Only the rich diagnostic format shows the hint
+    fn error_with_hint(&mut self, err: &str, hint: &str) {
+        self.error_at_token(err, Some(hint), |s| s.assert_prev());
     }
-    fn error_at_token<F>(&mut self, err: &str, get_token: F)
+    fn error_at_token<F>(&mut self, err: &str, hint: Option<&str>, get_token: F)
     where
         F: Fn(&Self) -> &Token,
     {
         // This is taken as a callback so the caller doesn't have to borrow self.current or self.previous while also mutably borrowing self for this method
         let token = get_token(self);
 
-        let at = if token.kind == TokenKind::Eof {
-            " at end"
+        // An empty lexeme means the error is at the end of the source
+        let lexeme = if token.kind == TokenKind::Eof {
+            ""
         } else {
-            &format!(" at '{}'", token.lexeme)
+            &token.lexeme
         };
 
-        self.print_err(err, token.line, Some(at));
+        let mut diagnostic = Diagnostic::new(
+            DiagnosticKind::CompileError,
+            err,
+            token.line,
+            token.span,
+            self.scanner.source(),
+        )
+        .with_lexeme(lexeme);
+        if let Some(hint) = hint {
+            diagnostic = diagnostic.with_hint(hint);
+        }
+        self.report(diagnostic);
     }
 
-    fn print_err(&mut self, err: &str, line: usize, at: Option<&str>) {
+    fn report(&mut self, diagnostic: Diagnostic) {
         if self.panic_mode {
             return;
         }
-        let at_str = at.unwrap_or("");
-        eprintln!("[line {line}] Error{at_str}: {err}");
-
-        self.had_error = true;
+        self.diagnostics.push(diagnostic);
         self.panic_mode = true;
     }
 
@@ -157,13 +227,69 @@ impl<'a> Parser<'a> {
     }
 }
 
+// Starting and finishing the compilation of a function
 impl<'a> Parser<'a> {
+    fn begin_compiler(&mut self, kind: FunctionKind) {
+        // Function names are the identifier just before the parameter list
+        let name = self
+            .strings
+            .get_or_intern(&self.previous.as_ref().unwrap().lexeme);
+        let enclosing = std::mem::replace(&mut self.compiler, Compiler::new(kind, Some(name)));
+        self.compiler.enclosing = Some(Box::new(enclosing));
+    }
+    // Also returns the upvalues the function captures, which the enclosing function needs to emit for the closure
+    fn end_compiler(&mut self) -> (Function, Vec<UpvalueRef>) {
+        self.emit_return();
+        let (mut function, upvalues) = match self.compiler.enclosing.take() {
+            Some(enclosing) => {
+                let finished = std::mem::replace(&mut self.compiler, *enclosing);
+                (finished.function, finished.upvalues)
+            }
+            // The top-level script has nothing to go back to
+            None => (
+                std::mem::replace(&mut self.compiler.function, Function::new(None)),
+                vec![],
+            ),
+        };
+        function.upvalue_count = upvalues.len();
+        function.chunk.source = self.scanner.source().clone();
+        if cfg!(feature = "DEBUG_PRINT_CODE") && self.diagnostics.is_empty() {
+            function
+                .chunk
+                .disassemble(&function.to_string(), self.debug_output);
+        }
+        (function, upvalues)
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.compiler.function.chunk
+    }
     // Used to get the position of the ip for the current code - used to calculate jumps
     fn pos(&self) -> usize {
-        self.chunk.code.len()
+        self.compiler.function.chunk.code.len()
     }
     fn emit_ins(&mut self, ins: Op) {
-        self.chunk.write(ins, self.assert_prev().line);
+        let token = self.assert_prev();
+        let (line, span) = (token.line, token.span);
+        self.chunk().write(ins, line, span);
+    }
+    // For instructions whose errors are about an earlier token than the one just parsed,
+    //   like the operator of a binary expression, which comes before its right operand
+    //   The line table still uses the last token parsed, so runtime errors keep reporting the same lines
+    fn emit_ins_at(&mut self, ins: Op, token: &Token) {
+        let line = self.assert_prev().line;
+        self.chunk().write(ins, line, token.span);
+    }
+    fn emit_return(&mut self) {
+        if self.compiler.kind == FunctionKind::Initializer {
+            // Initializers always return the instance, which lives in slot zero
+            self.emit_ins(Op::GetLocal(0));
+        } else {
+            self.emit_ins(Op::Nil);
+        }
+        self.emit_ins(Op::Return);
     }
     #[must_use]
     fn emit_jump<JumpIns: FnOnce(u16) -> Op>(&mut self, jump: JumpIns) -> usize {
@@ -172,18 +298,24 @@ impl<'a> Parser<'a> {
         self.pos()
     }
     fn patch_jump(&mut self, jump_from: usize) {
-        let jump = (self.pos() - jump_from) as u16;
+        let Ok(jump) = u16::try_from(self.pos() - jump_from) else {
+            self.error("Too much code to jump over.");
+            return;
+        };
         let [upper, lower] = jump.to_be_bytes();
         // jump_from is the index after the jump operation was written, so the code to patch
         // are the two places before it
-        self.chunk.code[jump_from - 2] = upper;
-        self.chunk.code[jump_from - 1] = lower;
+        self.chunk().code[jump_from - 2] = upper;
+        self.chunk().code[jump_from - 1] = lower;
     }
     fn emit_loop(&mut self, loop_to: usize) {
-        self.emit_ins(Op::Loop((self.pos() - loop_to) as u16));
+        match u16::try_from(self.pos() - loop_to) {
+            Ok(go_back) => self.emit_ins(Op::Loop(go_back)),
+            Err(_) => self.error("Loop body too large."),
+        }
     }
-    fn make_constant(&mut self, val: Value) -> Option<u8> {
-        let res = self.chunk.add_constant(val);
+    fn make_constant(&mut self, val: Value) -> Option<usize> {
+        let res = self.chunk().add_constant(val);
         res.or_else(|| {
             self.error("Too many constants in one chunk.");
             None
@@ -191,13 +323,88 @@ impl<'a> Parser<'a> {
     }
     fn emit_constant(&mut self, val: Value) {
         match self.make_constant(val) {
-            Some(constant) => self.emit_ins(Op::Constant(constant)),
+            Some(constant) => self.emit_ins(Op::constant(constant)),
             None => { /* original code emits OP_CONSTANT 0 on error */ }
         }
     }
-    fn identifier_constant(&mut self) -> Option<u8> {
+    fn identifier_constant(&mut self) -> Option<usize> {
         let var_name = &self.previous.as_ref().unwrap().lexeme;
         let val = self.strings.build_string_value(var_name);
         self.make_constant(val)
     }
+    fn global_slot(&mut self, name: &str) -> Option<usize> {
+        let slot = self.globals.resolve(self.strings.get_or_intern(name));
+        if slot >= GLOBAL_SLOT_MAX {
+            self.error("Too many global variables.");
+            return None;
+        }
+        Some(slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::Opcode;
+
+    // With a local `x`, each statement compiles to exactly 8 bytes (no constants involved)
+    fn body(statements: usize) -> String {
+        "x = x + x;".repeat(statements)
+    }
+
+    fn compiles(source: String) -> bool {
+        compile(
+            source,
+            &mut StringInterns::new(),
+            &mut Globals::new(),
+            &mut std::io::sink(),
+        )
+        .is_ok()
+    }
+
+    #[test]
+    fn jump_too_large() {
+        let body = body(10_000);
+        assert!(!compiles(format!("{{ var x = 0; if (x) {{ {body} }} }}")));
+    }
+
+    #[test]
+    fn loop_too_large() {
+        let body = body(10_000);
+        assert!(!compiles(format!(
+            "{{ var x = 0; while (x) {{ {body} }} }}"
+        )));
+        assert!(!compiles(format!(
+            "{{ var x = 0; for (;x;) {{ {body} }} }}"
+        )));
+    }
+
+    #[test]
+    fn large_body_under_limit() {
+        // 64,000 bytes, just under the 65,535 a jump can cover
+        let body = body(8_000);
+        assert!(compiles(format!("{{ var x = 0; while (x) {{ {body} }} }}")));
+    }
+
+    #[test]
+    fn instructions_point_at_their_operator() {
+        let function = compile(
+            "var a = 1;\nprint a +\n  -a;".to_string(),
+            &mut StringInterns::new(),
+            &mut Globals::new(),
+            &mut std::io::sink(),
+        )
+        .unwrap();
+        let chunk = &function.chunk;
+        // Constant, DefineGlobal, GetGlobal and GetGlobal take two bytes each, then Negate and Add
+        assert_eq!(chunk.code[8], u8::from(Opcode::Negate));
+        assert_eq!(chunk.code[9], u8::from(Opcode::Add));
+
+        let negate = chunk.spans[8];
+        assert_eq!((negate.line, negate.column), (3, 3));
+        // The operator, rather than the right operand parsed just before the instruction was emitted
+        let add = chunk.spans[9];
+        assert_eq!((add.line, add.column, add.start, add.end), (2, 9, 19, 20));
+        assert_eq!(chunk.lines[9], 3);
+    }
 }
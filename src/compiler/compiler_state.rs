@@ -1,4 +1,8 @@
-use crate::scanner::Token;
+use std::rc::Rc;
+
+use crate::instructions::UpvalueRef;
+use crate::scanner::{Span, Token, TokenKind};
+use crate::value::{Function, InternString};
 
 const UINT8_COUNT: usize = 256;
 
@@ -10,50 +14,112 @@ fn safe_decrement(val: usize) -> Option<usize> {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionKind {
+    Function,
+    Initializer,
+    Method,
+    Script,
+}
+
 #[derive(Debug)]
 pub struct Compiler {
+    // The compiler of the function this one is nested in, None for the top-level script
+    pub enclosing: Option<Box<Compiler>>,
+    pub function: Function,
+    pub kind: FunctionKind,
     pub scope_depth: usize,
     local_count: usize,
     locals: [Option<Local>; UINT8_COUNT],
+    pub upvalues: Vec<UpvalueRef>,
+    // The loops we're inside of in this function, innermost last
+    pub loops: Vec<LoopContext>,
+}
+
+#[derive(Debug)]
+pub struct LoopContext {
+    // Jumping out of the loop pops every local deeper than this
+    pub break_depth: usize,
+    // Continuing only pops locals of the loop body, which can be deeper than break_depth -
+    //   e.g. a for loop's per-iteration copy of its variable survives until the end of the body
+    pub continue_depth: usize,
+    // Both jump forwards, so are patched once the end of the body or loop is known
+    pub break_jumps: Vec<usize>,
+    pub continue_jumps: Vec<usize>,
 }
 
 #[derive(Debug)]
 struct Local {
     // depth is None for uninitialized variables
     depth: Option<usize>,
+    // Captured locals need to be moved off the stack when they go out of scope, instead of just popped
+    is_captured: bool,
     // In the book this would be a borrow, but I think tricky to prove that everything lives long enough
     name: Token,
 }
 
 impl Compiler {
-    pub fn new() -> Self {
-        Compiler {
+    pub fn new(kind: FunctionKind, name: Option<Rc<InternString>>) -> Self {
+        let mut compiler = Compiler {
+            enclosing: None,
+            function: Function::new(name),
+            kind,
             scope_depth: 0,
             local_count: 0,
             locals: [const { None }; UINT8_COUNT],
-        }
+            upvalues: vec![],
+            loops: vec![],
+        };
+        // Slot zero of every call frame holds the function being called, claim it so user locals start at one
+        // For methods, slot zero holds the instance instead, which is accessed as `this`
+        let slot_zero_name = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Function | FunctionKind::Script => "",
+        };
+        compiler.locals[0] = Some(Local {
+            depth: Some(0),
+            is_captured: false,
+            name: Token {
+                kind: TokenKind::Identifier,
+                lexeme: slot_zero_name.to_string(),
+                line: 0,
+                span: Span::default(),
+            },
+        });
+        compiler.local_count = 1;
+        compiler
     }
 
     pub fn begin_scope(&mut self) {
         self.scope_depth += 1;
     }
-    pub fn end_scope(&mut self) -> usize {
+    // Returns whether each removed local was captured by a closure, from the top of the stack down
+    pub fn end_scope(&mut self) -> Vec<bool> {
         self.scope_depth -= 1;
+        let scope_depth = self.scope_depth;
 
-        let mut count = 0;
+        let mut removed = vec![];
         loop {
             let opt_local = self.peek_local();
             if let Some(local) = opt_local {
-                if local.depth.expect("Ended scope with uninitialized local") <= self.scope_depth {
+                if local.depth.expect("Ended scope with uninitialized local") <= scope_depth {
                     break;
                 }
+                removed.push(local.is_captured);
                 self.pop_local();
-                count += 1;
             } else {
                 break;
             }
         }
-        count
+        removed
+    }
+    // Whether each local deeper than the depth was captured, from the top of the stack down
+    //   Used to clean up the stack when jumping out of scopes, without ending them
+    pub fn locals_deeper_than(&self, depth: usize) -> Vec<bool> {
+        self.iter_locals()
+            .take_while(|local| local.depth.is_some_and(|local_depth| local_depth > depth))
+            .map(|local| local.is_captured)
+            .collect()
     }
     pub fn add_local(&mut self, name: &Token) -> Result<(), &'static str> {
         if self.local_count == UINT8_COUNT {
@@ -68,6 +134,7 @@ impl Compiler {
         self.locals[self.local_count] = Some(Local {
             name: name.clone(),
             depth: None,
+            is_captured: false,
         });
         self.local_count += 1;
         Ok(())
@@ -79,11 +146,45 @@ impl Compiler {
             .expect("Attempted to mark initialized when no variable is being defined");
         local.depth = Some(depth);
     }
+    // Returns the slot of the local, relative to the base of the function's call frame
     pub fn resolve_local(&self, name: &Token) -> Option<(u8, bool)> {
         self.iter_locals()
             .enumerate()
             .find(|(_, local)| local.name.lexeme == name.lexeme)
-            .map(|(i, local)| (i as u8, local.depth.is_some()))
+            // The walker goes from the top of the locals down, so flip the index around
+            .map(|(i, local)| ((self.local_count - 1 - i) as u8, local.depth.is_some()))
+    }
+    // Looks for the variable in the enclosing functions, returning the index of the upvalue that captures it
+    pub fn resolve_upvalue(&mut self, name: &Token) -> Result<Option<u8>, &'static str> {
+        let Some(enclosing) = self.enclosing.as_mut() else {
+            return Ok(None);
+        };
+        if let Some((idx, initialized)) = enclosing.resolve_local(name) {
+            if !initialized {
+                return Err("Can't read local variable in its own initializer.");
+            }
+            enclosing.locals[idx as usize]
+                .as_mut()
+                .expect("resolved local should exist")
+                .is_captured = true;
+            return self.add_upvalue(idx, true).map(Some);
+        }
+        match enclosing.resolve_upvalue(name)? {
+            Some(idx) => self.add_upvalue(idx, false).map(Some),
+            None => Ok(None),
+        }
+    }
+    fn add_upvalue(&mut self, index: u8, is_local: bool) -> Result<u8, &'static str> {
+        let upvalue = UpvalueRef { is_local, index };
+        // Closures referencing the same variable multiple times share an upvalue
+        if let Some(existing) = self.upvalues.iter().position(|u| *u == upvalue) {
+            return Ok(existing as u8);
+        }
+        if self.upvalues.len() == UINT8_COUNT {
+            return Err("Too many closure variables in function.");
+        }
+        self.upvalues.push(upvalue);
+        Ok((self.upvalues.len() - 1) as u8)
     }
 
     fn peek_local(&mut self) -> Option<&mut Local> {
@@ -96,14 +197,14 @@ impl Compiler {
 }
 
 impl Compiler {
-    fn iter_same_depth_locals(&self) -> LocalWalker {
+    fn iter_same_depth_locals(&self) -> LocalWalker<'_> {
         LocalWalker {
             idx: safe_decrement(self.local_count),
             depth: Some(self.scope_depth),
             locals: &self.locals,
         }
     }
-    fn iter_locals(&self) -> LocalWalker {
+    fn iter_locals(&self) -> LocalWalker<'_> {
         LocalWalker {
             idx: safe_decrement(self.local_count),
             depth: None,
@@ -139,21 +240,22 @@ impl<'a> Iterator for LocalWalker<'a> {
 
 #[cfg(test)]
 mod tests {
-    use crate::scanner::{Token, TokenKind};
+    use crate::scanner::{Span, Token, TokenKind};
 
-    use super::Compiler;
+    use super::{Compiler, FunctionKind};
 
     fn id_token(name: String) -> Token {
         Token {
             kind: TokenKind::Identifier,
             lexeme: name,
             line: 0,
+            span: Span::default(),
         }
     }
 
     #[test]
     fn locals_test() {
-        let mut compiler = Compiler::new();
+        let mut compiler = Compiler::new(FunctionKind::Script, None);
 
         let x = id_token("x".to_string());
         let y = id_token("y".to_string());
@@ -165,25 +267,44 @@ mod tests {
         compiler.add_local(&y).unwrap();
         compiler.mark_initialized();
 
-        assert_eq!(compiler.local_count, 2);
+        // Slot zero is reserved for the function itself
+        assert_eq!(compiler.local_count, 3);
 
-        assert_eq!(compiler.locals[0].as_ref().unwrap().name.lexeme, "x");
-        assert_eq!(compiler.locals[1].as_ref().unwrap().name.lexeme, "y");
+        assert_eq!(compiler.locals[1].as_ref().unwrap().name.lexeme, "x");
+        assert_eq!(compiler.locals[2].as_ref().unwrap().name.lexeme, "y");
 
         compiler.begin_scope();
         compiler.add_local(&z).unwrap();
         compiler.mark_initialized();
 
-        assert_eq!(compiler.local_count, 3);
-        assert_eq!(compiler.locals[2].as_ref().unwrap().name.lexeme, "z");
+        assert_eq!(compiler.local_count, 4);
+        assert_eq!(compiler.locals[3].as_ref().unwrap().name.lexeme, "z");
 
         compiler.end_scope();
-        assert_eq!(compiler.local_count, 2);
-        assert!(compiler.locals[2].is_none());
+        assert_eq!(compiler.local_count, 3);
+        assert!(compiler.locals[3].is_none());
 
         compiler.end_scope();
-        assert_eq!(compiler.local_count, 0);
-        assert!(compiler.locals[1].is_none());
+        assert_eq!(compiler.local_count, 1);
+        assert!(compiler.locals[2].is_none());
         assert!(compiler.locals[1].is_none());
     }
+
+    #[test]
+    fn resolves_slots_from_frame_base() {
+        let mut compiler = Compiler::new(FunctionKind::Script, None);
+
+        let x = id_token("x".to_string());
+        let y = id_token("y".to_string());
+
+        compiler.begin_scope();
+        compiler.add_local(&x).unwrap();
+        compiler.mark_initialized();
+        compiler.add_local(&y).unwrap();
+
+        // Slot numbers count up from the function in slot zero, regardless of how many locals come after
+        assert_eq!(compiler.resolve_local(&x), Some((1, true)));
+        assert_eq!(compiler.resolve_local(&y), Some((2, false)));
+        assert_eq!(compiler.resolve_local(&id_token("z".to_string())), None);
+    }
 }
@@ -1,10 +1,16 @@
-use crate::{instructions::Op, scanner::TokenKind, value::Value};
+use std::rc::Rc;
 
-use super::Parser;
+use crate::{
+    instructions::Op,
+    scanner::{parse_number, unescape, Token, TokenKind},
+    value::Value,
+};
 
-/// This mod contains the majority of the actual language grammar parsing logic
-/// The 'parser API' lives in compiler.rs (oddly Parser is the central struct, not Compiler),
-/// This mod leverages it to create the language
+use super::{synthetic_token, ClassCompiler, FunctionKind, LoopContext, Parser};
+
+// This mod contains the majority of the actual language grammar parsing logic
+// The 'parser API' lives in compiler.rs (oddly Parser is the central struct, not Compiler),
+// This mod leverages it to create the language
 
 impl<'a> Parser<'a> {
     // Parses everything at the given precedence level (or higher)
@@ -31,16 +37,22 @@ impl<'a> Parser<'a> {
         }
 
         if can_assign && self.match_t(TokenKind::Equal) {
-            self.error("Invalid assignment target.");
+            self.error_with_hint(
+                "Invalid assignment target.",
+                "Only variables, properties and list or map elements can be assigned to.",
+            );
         }
     }
 
     // This is a bit of a workaround - we need to emit a Pop for each variable that goes out of scope
     //  but I don't want to have to nest all the compiler logic in here with instruction emitting
     fn end_scope(&mut self) {
-        let removed_count = self.compiler.end_scope();
-        for _ in 0..removed_count {
-            self.emit_ins(Op::Pop);
+        for is_captured in self.compiler.end_scope() {
+            self.emit_ins(if is_captured {
+                Op::CloseUpvalue
+            } else {
+                Op::Pop
+            });
         }
     }
 
@@ -56,7 +68,11 @@ impl<'a> Parser<'a> {
     }
 
     pub fn declaration(&mut self) {
-        if self.match_t(TokenKind::Var) {
+        if self.match_t(TokenKind::Class) {
+            self.class_declaration();
+        } else if self.match_t(TokenKind::Fun) {
+            self.fun_declaration();
+        } else if self.match_t(TokenKind::Var) {
             self.variable_declaration();
         } else {
             self.statement();
@@ -65,23 +81,133 @@ impl<'a> Parser<'a> {
             self.synchronize()
         }
     }
+    fn class_declaration(&mut self) {
+        self.consume(TokenKind::Identifier, "Expect class name.");
+        let class_name = self.assert_prev().clone();
+        let name_constant = self.identifier_constant();
+        self.declare_variable();
+        let global_slot = if self.compiler.scope_depth > 0 {
+            None
+        } else {
+            self.global_slot(&class_name.lexeme)
+        };
+
+        if let Some(name_constant) = name_constant {
+            self.emit_ins(Op::class(name_constant));
+        }
+        self.define_variable(global_slot);
+
+        self.class_compilers.push(ClassCompiler {
+            has_superclass: false,
+        });
+
+        if self.match_t(TokenKind::Less) {
+            self.consume(TokenKind::Identifier, "Expect superclass name.");
+            if self.assert_prev().lexeme == class_name.lexeme {
+                self.error("A class can't inherit from itself.");
+            }
+            self.variable(false);
+
+            // The superclass is stored in a local named `super`, so methods can capture it as an upvalue
+            self.compiler.begin_scope();
+            let super_token = synthetic_token("super", class_name.line);
+            if let Err(err) = self.compiler.add_local(&super_token) {
+                self.error(err);
+            }
+            self.define_variable(None);
+
+            self.named_variable(class_name.clone(), false);
+            self.emit_ins(Op::Inherit);
+            self.class_compilers
+                .last_mut()
+                .expect("should be in a class")
+                .has_superclass = true;
+        }
+
+        // Load the class back onto the stack so the methods can be attached to it
+        self.named_variable(class_name, false);
+        self.consume(TokenKind::LeftBrace, "Expect '{' before class body.");
+        while !self.check(TokenKind::RightBrace) && !self.check(TokenKind::Eof) {
+            self.method();
+        }
+        self.consume(TokenKind::RightBrace, "Expect '}' after class body.");
+        self.emit_ins(Op::Pop);
+
+        let class_compiler = self.class_compilers.pop().expect("should be in a class");
+        if class_compiler.has_superclass {
+            self.end_scope();
+        }
+    }
+    fn method(&mut self) {
+        self.consume(TokenKind::Identifier, "Expect method name.");
+        let name_constant = self.identifier_constant();
+        let kind = if self.assert_prev().lexeme == "init" {
+            FunctionKind::Initializer
+        } else {
+            FunctionKind::Method
+        };
+        self.function(kind);
+        if let Some(name_constant) = name_constant {
+            self.emit_ins(Op::method(name_constant));
+        }
+    }
+    fn fun_declaration(&mut self) {
+        let global = self.parse_variable("Expect function name.");
+        // Unlike variables, a function can refer to itself (for recursion) so it's initialized before the body
+        if self.compiler.scope_depth > 0 {
+            self.compiler.mark_initialized();
+        }
+        self.function(FunctionKind::Function);
+        self.define_variable(global);
+    }
+    fn function(&mut self, kind: FunctionKind) {
+        self.begin_compiler(kind);
+        // No matching end_scope - the whole call frame is discarded on return
+        self.compiler.begin_scope();
+
+        self.consume(TokenKind::LeftParen, "Expect '(' after function name.");
+        if !self.check(TokenKind::RightParen) {
+            loop {
+                if self.compiler.function.arity == u8::MAX {
+                    self.error_at_current("Can't have more than 255 parameters.");
+                } else {
+                    self.compiler.function.arity += 1;
+                }
+                let param_constant = self.parse_variable("Expect parameter name.");
+                self.define_variable(param_constant);
+                if !self.match_t(TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenKind::RightParen, "Expect ')' after parameters.");
+        self.consume(TokenKind::LeftBrace, "Expect '{' before function body.");
+        self.block();
+
+        let (function, upvalues) = self.end_compiler();
+        let const_idx = self.make_constant(Value::Function(Rc::new(function)));
+        if let Some(const_idx) = const_idx {
+            self.emit_ins(Op::closure(const_idx, upvalues));
+        }
+    }
     fn variable_declaration(&mut self) {
-        let variable_constant = self.parse_variable("Expect variable name.");
+        let variable_slot = self.parse_variable("Expect variable name.");
         if self.match_t(TokenKind::Equal) {
             self.expression();
         } else {
             self.emit_ins(Op::Nil);
         }
         self.consume(TokenKind::Semicolon, "Expect ';' after assignment.");
-        self.define_variable(variable_constant)
+        self.define_variable(variable_slot)
     }
-    fn parse_variable(&mut self, err: &str) -> Option<u8> {
+    fn parse_variable(&mut self, err: &str) -> Option<usize> {
         self.consume(TokenKind::Identifier, err);
         self.declare_variable();
         if self.compiler.scope_depth > 0 {
             return None;
         }
-        self.identifier_constant()
+        let name = self.assert_prev().lexeme.clone();
+        self.global_slot(&name)
     }
     fn declare_variable(&mut self) {
         // don't register globals
@@ -95,15 +221,15 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn define_variable(&mut self, maybe_global_idx: Option<u8>) {
+    fn define_variable(&mut self, maybe_global_slot: Option<usize>) {
         if self.compiler.scope_depth > 0 {
             // To 'define' a local variable, just leave the value on top of the ValueStack
             self.compiler.mark_initialized();
             return;
         }
-        self.emit_ins(match maybe_global_idx {
-            Some(const_idx) => Op::DefineGlobal(const_idx),
-            // only hit this case if we have too many constants - just emit a pop to ignore the value that would be defined
+        self.emit_ins(match maybe_global_slot {
+            Some(slot) => Op::define_global(slot),
+            // only hit this case if we have too many globals - just emit a pop to ignore the value that would be defined
             _ => Op::Pop,
         });
     }
@@ -112,10 +238,16 @@ impl<'a> Parser<'a> {
             self.print_statement();
         } else if self.match_t(TokenKind::If) {
             self.if_statement();
+        } else if self.match_t(TokenKind::Return) {
+            self.return_statement();
         } else if self.match_t(TokenKind::While) {
             self.while_statement();
         } else if self.match_t(TokenKind::For) {
             self.for_statement();
+        } else if self.match_t(TokenKind::Break) {
+            self.break_statement();
+        } else if self.match_t(TokenKind::Continue) {
+            self.continue_statement();
         } else if self.match_t(TokenKind::LeftBrace) {
             self.compiler.begin_scope();
             self.block();
@@ -129,6 +261,24 @@ impl<'a> Parser<'a> {
         self.consume(TokenKind::Semicolon, "Expect ';' after value.");
         self.emit_ins(Op::Print);
     }
+    fn return_statement(&mut self) {
+        if self.compiler.kind == FunctionKind::Script {
+            self.error("Can't return from top-level code.");
+        }
+        if self.match_t(TokenKind::Semicolon) {
+            self.emit_return();
+        } else {
+            if self.compiler.kind == FunctionKind::Initializer {
+                self.error_with_hint(
+                    "Can't return a value from an initializer.",
+                    "Initializers always return 'this'; use a bare 'return;' to leave early.",
+                );
+            }
+            self.expression();
+            self.consume(TokenKind::Semicolon, "Expect ';' after return value.");
+            self.emit_ins(Op::Return);
+        }
+    }
     fn if_statement(&mut self) {
         self.consume(TokenKind::LeftParen, "Expect '(' after 'if'.");
         self.expression();
@@ -159,20 +309,29 @@ impl<'a> Parser<'a> {
 
         let exit_jump = self.emit_jump(Op::JumpIfFalse);
         self.emit_ins(Op::Pop);
+        self.begin_loop();
         self.statement();
+        self.patch_continues();
         self.emit_loop(jump_back_target);
         self.patch_jump(exit_jump);
         self.emit_ins(Op::Pop);
+        self.end_loop();
     }
     fn for_statement(&mut self) {
         self.compiler.begin_scope();
         self.consume(TokenKind::LeftParen, "Expect '(' after 'for'.");
         // initializer
+        let mut loop_var = None;
         match self.current.kind {
             TokenKind::Semicolon => self.advance(),
             TokenKind::Var => {
                 self.advance();
+                let name = self.current.clone();
                 self.variable_declaration();
+                loop_var = self
+                    .compiler
+                    .resolve_local(&name)
+                    .map(|(slot, _)| (name, slot));
             }
             _ => self.expression_statement(),
         }
@@ -209,7 +368,8 @@ impl<'a> Parser<'a> {
             }
         }
 
-        self.statement();
+        self.begin_loop();
+        self.for_body(loop_var);
         // Loop back, either to the start of the updater, if there is one
         self.emit_loop(loop_start);
 
@@ -217,8 +377,93 @@ impl<'a> Parser<'a> {
             self.patch_jump(exit_jump);
             self.emit_ins(Op::Pop);
         }
+        self.end_loop();
+
+        self.end_scope();
+    }
+    // Each iteration gets its own copy of the loop variable, so closures in the body capture that iteration's value
+    //   The copy is written back to the real loop variable at the end of the body, for the condition and updater
+    fn for_body(&mut self, loop_var: Option<(Token, u8)>) {
+        self.compiler.begin_scope();
+        let copy_slot = loop_var.and_then(|(name, outer_slot)| {
+            self.emit_ins(Op::GetLocal(outer_slot));
+            if let Err(err) = self.compiler.add_local(&name) {
+                self.error(err);
+                return None;
+            }
+            self.compiler.mark_initialized();
+            self.compiler
+                .resolve_local(&name)
+                .map(|(copy_slot, _)| (copy_slot, outer_slot))
+        });
+        // `continue` keeps the copy around, so it still gets written back
+        let depth = self.compiler.scope_depth;
+        self.loop_context().continue_depth = depth;
+
+        self.statement();
+        self.patch_continues();
 
-        self.compiler.end_scope();
+        if let Some((copy_slot, outer_slot)) = copy_slot {
+            self.emit_ins(Op::GetLocal(copy_slot));
+            self.emit_ins(Op::SetLocal(outer_slot));
+            self.emit_ins(Op::Pop);
+        }
+        self.end_scope();
+    }
+    fn break_statement(&mut self) {
+        let Some(depth) = self.compiler.loops.last().map(|l| l.break_depth) else {
+            self.error("Can't use 'break' outside of a loop.");
+            return;
+        };
+        self.consume(TokenKind::Semicolon, "Expect ';' after 'break'.");
+        self.pop_locals_deeper_than(depth);
+        let jump = self.emit_jump(Op::Jump);
+        self.loop_context().break_jumps.push(jump);
+    }
+    fn continue_statement(&mut self) {
+        let Some(depth) = self.compiler.loops.last().map(|l| l.continue_depth) else {
+            self.error("Can't use 'continue' outside of a loop.");
+            return;
+        };
+        self.consume(TokenKind::Semicolon, "Expect ';' after 'continue'.");
+        self.pop_locals_deeper_than(depth);
+        let jump = self.emit_jump(Op::Jump);
+        self.loop_context().continue_jumps.push(jump);
+    }
+    // The locals stay declared, since the code after the jump is still in their scope
+    fn pop_locals_deeper_than(&mut self, depth: usize) {
+        for is_captured in self.compiler.locals_deeper_than(depth) {
+            self.emit_ins(if is_captured {
+                Op::CloseUpvalue
+            } else {
+                Op::Pop
+            });
+        }
+    }
+    fn begin_loop(&mut self) {
+        let depth = self.compiler.scope_depth;
+        self.compiler.loops.push(LoopContext {
+            break_depth: depth,
+            continue_depth: depth,
+            break_jumps: vec![],
+            continue_jumps: vec![],
+        });
+    }
+    fn loop_context(&mut self) -> &mut LoopContext {
+        self.compiler.loops.last_mut().expect("should be in a loop")
+    }
+    // Continues jump to the end of the body, rather than back to the start, so the body can clean up first
+    fn patch_continues(&mut self) {
+        let jumps = std::mem::take(&mut self.loop_context().continue_jumps);
+        for jump in jumps {
+            self.patch_jump(jump);
+        }
+    }
+    fn end_loop(&mut self) {
+        let context = self.compiler.loops.pop().expect("should be in a loop");
+        for jump in context.break_jumps {
+            self.patch_jump(jump);
+        }
     }
     fn expression_statement(&mut self) {
         self.expression();
@@ -229,42 +474,78 @@ impl<'a> Parser<'a> {
     fn number(&mut self, _: bool) {
         // kind of awkward that we just read previous and hope it's a Number token, but I don't want to go crazy
         // on architecture changes here
-        let val = self
-            .assert_prev()
-            .lexeme
-            .parse::<f64>()
-            .expect("Tried to parse a number but failed");
-        self.emit_constant(Value::Number(val));
+        match parse_number(&self.assert_prev().lexeme) {
+            Some(val) => self.emit_constant(Value::Number(val)),
+            None => self.error("Invalid number literal."),
+        }
     }
     fn string(&mut self, _: bool) {
-        let raw_str = &self.previous.as_ref().unwrap().lexeme;
-        let val = self
-            .strings
-            .build_string_value(&raw_str[1..raw_str.len() - 1]); // slice off quotes
+        self.string_segment();
+    }
+    // "a ${b} c" compiles to the same thing as "a " + b + " c", except b is converted to a string first
+    fn interpolation(&mut self, _: bool) {
+        self.string_segment();
+        loop {
+            self.expression();
+            self.emit_ins(Op::Stringify);
+            self.emit_ins(Op::Add);
+            if self.match_t(TokenKind::Interpolation) {
+                self.string_segment();
+                self.emit_ins(Op::Add);
+            } else {
+                break;
+            }
+        }
+        self.consume(
+            TokenKind::String,
+            "Expect '}' after interpolated expression.",
+        );
+        self.string_segment();
+        self.emit_ins(Op::Add);
+    }
+    // Emits the literal part of a string token - everything between the quotes, or the braces around interpolations
+    fn string_segment(&mut self) {
+        let token = self.assert_prev();
+        let end_len = if token.kind == TokenKind::Interpolation {
+            2 // `${`
+        } else {
+            1 // `"`
+        };
+        let raw_str = &token.lexeme[1..token.lexeme.len() - end_len];
+        let contents = unescape(raw_str).expect("scanner should have rejected bad escapes");
+        let val = self.strings.build_string_value(&contents);
         self.emit_constant(val);
     }
     fn variable(&mut self, can_assign: bool) {
-        self.named_variable(can_assign);
+        // Cloned since resolving an upvalue needs the name while the error reporting needs self
+        let var_name = self.assert_prev().clone();
+        self.named_variable(var_name, can_assign);
     }
-    fn named_variable(&mut self, can_assign: bool) {
-        let var_name = self.assert_prev();
+    fn named_variable(&mut self, var_name: Token, can_assign: bool) {
         let (set_op, get_op) = self
             .compiler
-            .resolve_local(var_name)
+            .resolve_local(&var_name)
             .map(|(idx, initialized)| {
                 if !initialized {
                     self.error("Can't read local variable in its own initializer.")
                 }
                 (Op::SetLocal(idx), Op::GetLocal(idx))
             })
+            .or_else(|| match self.compiler.resolve_upvalue(&var_name) {
+                Ok(upvalue) => upvalue.map(|idx| (Op::SetUpvalue(idx), Op::GetUpvalue(idx))),
+                Err(err) => {
+                    self.error(err);
+                    None
+                }
+            })
             .or_else(|| {
-                self.identifier_constant()
-                    .map(|idx| (Op::SetGlobal(idx), Op::GetGlobal(idx)))
+                self.global_slot(&var_name.lexeme)
+                    .map(|slot| (Op::set_global(slot), Op::get_global(slot)))
             })
             .unwrap_or((Op::Pop, Op::Nil));
         if can_assign && self.match_t(TokenKind::Equal) {
             self.expression();
-            self.emit_ins(set_op);
+            self.emit_ins_at(set_op, &var_name);
         } else {
             self.emit_ins(get_op);
         }
@@ -274,45 +555,185 @@ impl<'a> Parser<'a> {
         self.consume(TokenKind::RightParen, "Expected ')' after expression.");
     }
     fn unary(&mut self, _: bool) {
-        let op = match self.assert_prev().kind {
+        let operator = self.assert_prev().clone();
+        let op = match operator.kind {
             TokenKind::Minus => Op::Negate,
             TokenKind::Bang => Op::Not,
+            TokenKind::Tilde => Op::BitNot,
             _ => {
                 self.error("Expected unary operator.");
                 return;
             }
         };
         self.parse_precedence(ParsePrecedence::Unary);
-        self.emit_ins(op);
+        self.emit_ins_at(op, &operator);
     }
 
     fn binary(&mut self, _: bool) {
         // lhs side has already been parsed
 
-        let operator = self.assert_prev().kind;
+        let operator_token = self.assert_prev().clone();
+        let operator = operator_token.kind;
 
         let rule = Parser::get_rule(operator);
         let precedence = rule
             .precedence
             .expect("Couldn't get precedence for binary operator");
 
-        self.parse_precedence(precedence.next());
-        self.emit_ins(match operator {
+        if operator == TokenKind::StarStar {
+            // Right associative, and binds tighter than a unary minus on its left but not its right: -2 ** -2 is -(2 ** (-2))
+            self.parse_precedence(ParsePrecedence::Unary);
+        } else {
+            self.parse_precedence(precedence.next());
+        }
+        let op = match operator {
             TokenKind::Plus => Op::Add,
             TokenKind::Minus => Op::Subtract,
             TokenKind::Star => Op::Multiply,
             TokenKind::Slash => Op::Divide,
+            TokenKind::Percent => Op::Modulo,
+            TokenKind::TildeSlash => Op::FloorDivide,
+            TokenKind::StarStar => Op::Power,
+            TokenKind::Ampersand => Op::BitAnd,
+            TokenKind::Pipe => Op::BitOr,
+            TokenKind::Caret => Op::BitXor,
+            TokenKind::LessLess => Op::ShiftLeft,
+            TokenKind::GreaterGreater => Op::ShiftRight,
             // !=, <=, >= are done with two ops, this one, followed by a not
             TokenKind::Less | TokenKind::GreaterEqual => Op::Less,
             TokenKind::Greater | TokenKind::LessEqual => Op::Greater,
             TokenKind::EqualEqual | TokenKind::BangEqual => Op::Equal,
             _ => panic!("Unexpected token as binary operator"),
-        });
+        };
+        self.emit_ins_at(op, &operator_token);
         if matches!(
             operator,
             TokenKind::GreaterEqual | TokenKind::LessEqual | TokenKind::BangEqual,
         ) {
-            self.emit_ins(Op::Not);
+            self.emit_ins_at(Op::Not, &operator_token);
+        }
+    }
+    fn call(&mut self, _: bool) {
+        let paren = self.assert_prev().clone();
+        let arg_count = self.argument_list();
+        self.emit_ins_at(Op::Call(arg_count), &paren);
+    }
+    fn argument_list(&mut self) -> u8 {
+        let mut arg_count: u8 = 0;
+        if !self.check(TokenKind::RightParen) {
+            loop {
+                self.expression();
+                if arg_count == u8::MAX {
+                    self.error("Can't have more than 255 arguments.");
+                } else {
+                    arg_count += 1;
+                }
+                if !self.match_t(TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenKind::RightParen, "Expect ')' after arguments.");
+        arg_count
+    }
+    fn dot(&mut self, can_assign: bool) {
+        self.consume(TokenKind::Identifier, "Expect property name after '.'.");
+        let name = self.assert_prev().clone();
+        let name_constant = self.identifier_constant();
+        let Some(name_constant) = name_constant else {
+            return;
+        };
+        if can_assign && self.match_t(TokenKind::Equal) {
+            self.expression();
+            self.emit_ins_at(Op::set_property(name_constant), &name);
+        } else {
+            self.emit_ins(Op::get_property(name_constant));
+        }
+    }
+    fn list(&mut self, _: bool) {
+        let mut item_count: u8 = 0;
+        if !self.check(TokenKind::RightBracket) {
+            loop {
+                self.expression();
+                if item_count == u8::MAX {
+                    self.error("Can't have more than 255 items in a list literal.");
+                } else {
+                    item_count += 1;
+                }
+                if !self.match_t(TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenKind::RightBracket, "Expect ']' after list items.");
+        self.emit_ins(Op::BuildList(item_count));
+    }
+    // Keys that are bare identifiers are taken as strings, like `{name: "value"}` - any other expression is evaluated
+    fn map(&mut self, _: bool) {
+        let mut entry_count: u8 = 0;
+        if !self.check(TokenKind::RightBrace) {
+            loop {
+                // A bare name is a string key, but only when it's the whole key - `{a + b: 1}` uses the variable
+                if self.check(TokenKind::Identifier) && self.check_next(TokenKind::Colon) {
+                    self.advance();
+                    let key = self.assert_prev().lexeme.clone();
+                    let val = self.strings.build_string_value(&key);
+                    self.emit_constant(val);
+                } else {
+                    self.expression();
+                }
+                self.consume(TokenKind::Colon, "Expect ':' after map key.");
+                self.expression();
+                if entry_count == u8::MAX {
+                    self.error("Can't have more than 255 entries in a map literal.");
+                } else {
+                    entry_count += 1;
+                }
+                if !self.match_t(TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenKind::RightBrace, "Expect '}' after map entries.");
+        self.emit_ins(Op::BuildMap(entry_count));
+    }
+    fn subscript(&mut self, can_assign: bool) {
+        let bracket = self.assert_prev().clone();
+        self.expression();
+        self.consume(TokenKind::RightBracket, "Expect ']' after index.");
+        if can_assign && self.match_t(TokenKind::Equal) {
+            self.expression();
+            self.emit_ins_at(Op::SetIndex, &bracket);
+        } else {
+            self.emit_ins_at(Op::GetIndex, &bracket);
+        }
+    }
+    fn this(&mut self, _: bool) {
+        if self.class_compilers.is_empty() {
+            self.error("Can't use 'this' outside of a class.");
+            return;
+        }
+        // `this` is just a local variable in slot zero of methods, can't be assigned to
+        self.variable(false);
+    }
+    fn super_(&mut self, _: bool) {
+        match self.class_compilers.last() {
+            None => self.error("Can't use 'super' outside of a class."),
+            Some(ClassCompiler {
+                has_superclass: false,
+            }) => self.error("Can't use 'super' in a class with no superclass."),
+            Some(_) => {}
+        }
+        self.consume(TokenKind::Dot, "Expect '.' after 'super'.");
+        self.consume(TokenKind::Identifier, "Expect superclass method name.");
+        let name_constant = self.identifier_constant();
+
+        let line = self.assert_prev().line;
+        // Look up the method on the superclass, binding it to the current instance
+        self.named_variable(synthetic_token("this", line), false);
+        self.named_variable(synthetic_token("super", line), false);
+        if let Some(name_constant) = name_constant {
+            self.emit_ins(Op::get_super(name_constant));
         }
     }
     fn literal(&mut self, _: bool) {
@@ -349,12 +770,18 @@ enum ParsePrecedence {
     Assignment, // =
     Or,         // or
     And,        // and
+    // The bitwise operators sit below equality like they do in C, so `a & b == c` is `a & (b == c)`
+    BitOr,      // |
+    BitXor,     // ^
+    BitAnd,     // &
     Equality,   // == !=
     Comparison, // < > <= >=
+    Shift,      // << >>
     Term,       // + -
-    Factor,     // * /
+    Factor,     // * / % ~/
     Unary,      // ! -
-    Call,       // . ()
+    Exponent,   // **
+    Call,       // . () []
     Primary,
 }
 impl ParsePrecedence {
@@ -362,12 +789,17 @@ impl ParsePrecedence {
         match self {
             ParsePrecedence::Assignment => ParsePrecedence::Or,
             ParsePrecedence::Or => ParsePrecedence::And,
-            ParsePrecedence::And => ParsePrecedence::Equality,
+            ParsePrecedence::And => ParsePrecedence::BitOr,
+            ParsePrecedence::BitOr => ParsePrecedence::BitXor,
+            ParsePrecedence::BitXor => ParsePrecedence::BitAnd,
+            ParsePrecedence::BitAnd => ParsePrecedence::Equality,
             ParsePrecedence::Equality => ParsePrecedence::Comparison,
-            ParsePrecedence::Comparison => ParsePrecedence::Term,
+            ParsePrecedence::Comparison => ParsePrecedence::Shift,
+            ParsePrecedence::Shift => ParsePrecedence::Term,
             ParsePrecedence::Term => ParsePrecedence::Factor,
             ParsePrecedence::Factor => ParsePrecedence::Unary,
-            ParsePrecedence::Unary => ParsePrecedence::Call,
+            ParsePrecedence::Unary => ParsePrecedence::Exponent,
+            ParsePrecedence::Exponent => ParsePrecedence::Call,
             ParsePrecedence::Call => ParsePrecedence::Primary,
             ParsePrecedence::Primary => ParsePrecedence::Primary,
         }
@@ -420,7 +852,16 @@ impl<'a> Parser<'a> {
     fn get_rule(kind: TokenKind) -> ParseRule<'a> {
         match kind {
             TokenKind::LeftParen => {
-                parse_rule!(grouping, None, None)
+                parse_rule!(grouping, call, Call)
+            }
+            TokenKind::Dot => {
+                parse_rule!(None, dot, Call)
+            }
+            TokenKind::LeftBrace => {
+                parse_rule!(map, None, None)
+            }
+            TokenKind::LeftBracket => {
+                parse_rule!(list, subscript, Call)
             }
             TokenKind::Minus => {
                 parse_rule!(unary, binary, Term)
@@ -428,12 +869,27 @@ impl<'a> Parser<'a> {
             TokenKind::Plus => {
                 parse_rule!(None, binary, Term)
             }
-            TokenKind::Slash | TokenKind::Star => {
+            TokenKind::Slash | TokenKind::Star | TokenKind::Percent | TokenKind::TildeSlash => {
                 parse_rule!(None, binary, Factor)
             }
-            TokenKind::Bang => {
+            TokenKind::StarStar => {
+                parse_rule!(None, binary, Exponent)
+            }
+            TokenKind::Bang | TokenKind::Tilde => {
                 parse_rule!(unary, None, None)
             }
+            TokenKind::Pipe => {
+                parse_rule!(None, binary, BitOr)
+            }
+            TokenKind::Caret => {
+                parse_rule!(None, binary, BitXor)
+            }
+            TokenKind::Ampersand => {
+                parse_rule!(None, binary, BitAnd)
+            }
+            TokenKind::LessLess | TokenKind::GreaterGreater => {
+                parse_rule!(None, binary, Shift)
+            }
             TokenKind::BangEqual | TokenKind::EqualEqual => {
                 parse_rule!(None, binary, Equality)
             }
@@ -452,9 +908,18 @@ impl<'a> Parser<'a> {
             TokenKind::String => {
                 parse_rule!(string, None, None)
             }
+            TokenKind::Interpolation => {
+                parse_rule!(interpolation, None, None)
+            }
             TokenKind::Identifier => {
                 parse_rule!(variable, None, None)
             }
+            TokenKind::This => {
+                parse_rule!(this, None, None)
+            }
+            TokenKind::Super => {
+                parse_rule!(super_, None, None)
+            }
             TokenKind::And => {
                 parse_rule!(None, and, And)
             }
@@ -0,0 +1,268 @@
+use std::fmt::Write;
+
+use crate::scanner::Span;
+
+// How errors are written out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DiagnosticFormat {
+    // The book's one line format, e.g. `[line 1] Error at 'x': Expect ';' after value.`
+    #[default]
+    Plain,
+    // The message followed by the offending source line, with the token underlined
+    Rich {
+        colour: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    CompileError,
+    RuntimeError,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub message: String,
+    // The line the plain format reports, which for a token is the line it ends on
+    pub line: usize,
+    // None when there's nothing in the source to point at
+    pub span: Option<Span>,
+    // The token a compile error is at - empty for the end of the source, and None for errors that aren't at a token
+    pub lexeme: Option<String>,
+    // Extra advice, only shown in the rich format
+    pub hint: Option<String>,
+    // The text of the line the span starts on, so the error can be shown without the rest of the source
+    pub source_line: Option<String>,
+}
+
+// A runtime error, and the calls that were in progress when it happened
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub diagnostic: Diagnostic,
+    // Innermost call first
+    pub stack_trace: Vec<StackFrame>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StackFrame {
+    // None for the top-level script
+    pub function: Option<String>,
+    pub line: usize,
+}
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+impl Diagnostic {
+    pub fn new(
+        kind: DiagnosticKind,
+        message: &str,
+        line: usize,
+        span: Span,
+        source: &str,
+    ) -> Diagnostic {
+        // Synthetic tokens don't come from the source, and have a default span
+        let span = (span.line > 0 && span.end <= source.len()).then_some(span);
+        let source_line = span.map(|span| {
+            let line_start = source[..span.start].rfind('\n').map_or(0, |idx| idx + 1);
+            let line_end = source[span.start..]
+                .find('\n')
+                .map_or(source.len(), |idx| span.start + idx);
+            source[line_start..line_end]
+                .trim_end_matches('\r')
+                .to_string()
+        });
+        Diagnostic {
+            kind,
+            message: message.to_string(),
+            line,
+            span,
+            lexeme: None,
+            hint: None,
+            source_line,
+        }
+    }
+    pub fn with_lexeme(mut self, lexeme: &str) -> Diagnostic {
+        self.lexeme = Some(lexeme.to_string());
+        self
+    }
+    pub fn with_hint(mut self, hint: &str) -> Diagnostic {
+        self.hint = Some(hint.to_string());
+        self
+    }
+    // 1-based, counted in chars
+    pub fn column(&self) -> Option<usize> {
+        self.span.map(|span| span.column)
+    }
+
+    // Renders the diagnostic as lines of text, without a trailing newline
+    pub fn render(&self, format: DiagnosticFormat) -> String {
+        match format {
+            DiagnosticFormat::Plain => self.render_plain(),
+            DiagnosticFormat::Rich { colour } => self.render_rich(colour),
+        }
+    }
+    fn render_plain(&self) -> String {
+        match self.kind {
+            DiagnosticKind::CompileError => {
+                let at = match self.lexeme.as_deref() {
+                    None => String::new(),
+                    Some("") => " at end".to_string(),
+                    Some(lexeme) => format!(" at '{lexeme}'"),
+                };
+                format!("[line {}] Error{at}: {}", self.line, self.message)
+            }
+            DiagnosticKind::RuntimeError => self.message.clone(),
+        }
+    }
+    fn render_rich(&self, colour: bool) -> String {
+        let paint = |style: &'static str| if colour { style } else { "" };
+        let (red, blue, bold, reset) = (paint(RED), paint(BLUE), paint(BOLD), paint(RESET));
+        let label = match self.kind {
+            DiagnosticKind::CompileError => "error",
+            DiagnosticKind::RuntimeError => "runtime error",
+        };
+
+        let mut out = format!("{red}{label}{reset}{bold}: {}{reset}", self.message);
+        let (Some(span), Some(text)) = (self.span, &self.source_line) else {
+            let _ = write!(out, "\n {blue}-->{reset} line {}", self.line);
+            return out;
+        };
+        let token_start = text
+            .char_indices()
+            .nth(span.column - 1)
+            .map_or(text.len(), |(idx, _)| idx);
+        // Keep any tabs before the token, so the underline lines up however they're displayed
+        let padding: String = text[..token_start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        // Only the part of the token on its first line is underlined, and always at least one caret
+        let token_end = text.len().min(token_start + span.end - span.start);
+        let width = text[token_start..token_end].chars().count().max(1);
+        let number = span.line.to_string();
+        let gutter = " ".repeat(number.len());
+
+        let _ = write!(
+            out,
+            "\n{gutter}{blue}-->{reset} line {}, column {}",
+            span.line, span.column
+        );
+        let _ = write!(out, "\n{gutter} {blue}|{reset}");
+        let _ = write!(out, "\n{blue}{number} |{reset} {text}");
+        let _ = write!(
+            out,
+            "\n{gutter} {blue}|{reset} {padding}{red}{}{reset}",
+            "^".repeat(width)
+        );
+        if let Some(hint) = &self.hint {
+            let _ = write!(out, "\n{gutter} {blue}={reset} {bold}hint{reset}: {hint}");
+        }
+        out
+    }
+}
+
+impl RuntimeError {
+    // The error followed by the stack trace, one call per line
+    pub fn render(&self, format: DiagnosticFormat) -> String {
+        let mut out = self.diagnostic.render(format);
+        for frame in &self.stack_trace {
+            let _ = match &frame.function {
+                Some(name) => write!(out, "\n[line {}] in {name}()", frame.line),
+                None => write!(out, "\n[line {}] in script", frame.line),
+            };
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(start: usize, end: usize, line: usize, column: usize) -> Span {
+        Span {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    #[test]
+    fn plain_matches_the_book() {
+        let diagnostic = Diagnostic::new(
+            DiagnosticKind::CompileError,
+            "Expect ';' after value.",
+            1,
+            span(8, 9, 1, 9),
+            "print 1 x",
+        )
+        .with_lexeme("x")
+        .with_hint("Add a semicolon.");
+        assert_eq!(
+            diagnostic.render(DiagnosticFormat::Plain),
+            "[line 1] Error at 'x': Expect ';' after value."
+        );
+    }
+
+    #[test]
+    fn rich_underlines_the_token() {
+        let source = "var a = 1;\n\tprint a + \"b\";\n";
+        let diagnostic = Diagnostic::new(
+            DiagnosticKind::RuntimeError,
+            "Operands must be two numbers or two strings.",
+            2,
+            span(20, 21, 2, 10),
+            source,
+        )
+        .with_hint("Use a string template to join them.");
+        assert_eq!(
+            diagnostic.source_line.as_deref(),
+            Some("\tprint a + \"b\";")
+        );
+        assert_eq!(
+            diagnostic.render(DiagnosticFormat::Rich { colour: false }),
+            "runtime error: Operands must be two numbers or two strings.\n \
+             --> line 2, column 10\n  \
+             |\n\
+             2 | \tprint a + \"b\";\n  \
+             | \t        ^\n  \
+             = hint: Use a string template to join them."
+        );
+    }
+
+    #[test]
+    fn rich_without_span() {
+        let diagnostic = Diagnostic::new(
+            DiagnosticKind::CompileError,
+            "Too many constants in one chunk.",
+            3,
+            Span::default(),
+            "",
+        );
+        assert_eq!(
+            diagnostic.render(DiagnosticFormat::Rich { colour: false }),
+            "error: Too many constants in one chunk.\n --> line 3"
+        );
+    }
+
+    #[test]
+    fn plain_at_end() {
+        let diagnostic = Diagnostic::new(
+            DiagnosticKind::CompileError,
+            "Expect expression.",
+            2,
+            span(6, 6, 2, 1),
+            "print\n",
+        )
+        .with_lexeme("");
+        assert_eq!(
+            diagnostic.render(DiagnosticFormat::Plain),
+            "[line 2] Error at end: Expect expression."
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::value::{InternString, Value};
+
+// The compiler gives every global name a slot the first time it sees it, so the VM can load globals by index
+//   This outlives a single compile, so REPL lines and natives defined by the host all agree on the slots
+pub struct Globals {
+    slots: HashMap<Rc<InternString>, usize>,
+    // Kept for error messages, indexed by slot
+    names: Vec<Rc<InternString>>,
+    // None until the variable is defined - a slot can exist for a global that's only referenced so far
+    values: Vec<Option<Value>>,
+}
+
+impl Default for Globals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Globals {
+    pub fn new() -> Globals {
+        Globals {
+            slots: HashMap::new(),
+            names: vec![],
+            values: vec![],
+        }
+    }
+    // Finds the slot for the name, adding one if it's new
+    pub fn resolve(&mut self, name: Rc<InternString>) -> usize {
+        if let Some(&slot) = self.slots.get(&name) {
+            return slot;
+        }
+        let slot = self.names.len();
+        self.slots.insert(name.clone(), slot);
+        self.names.push(name);
+        self.values.push(None);
+        slot
+    }
+    pub fn name(&self, slot: usize) -> &Rc<InternString> {
+        &self.names[slot]
+    }
+    pub fn get(&self, slot: usize) -> Option<&Value> {
+        self.values[slot].as_ref()
+    }
+    pub fn define(&mut self, slot: usize, value: Value) {
+        self.values[slot] = Some(value);
+    }
+    // Assigning to a global that hasn't been defined fails, and leaves it undefined
+    pub fn set(&mut self, slot: usize, value: Value) -> Result<(), ()> {
+        match &mut self.values[slot] {
+            Some(existing) => {
+                *existing = value;
+                Ok(())
+            }
+            None => Err(()),
+        }
+    }
+    // The defined values, for the garbage collector
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.values.iter().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::value::{StringInterns, Value};
+
+    use super::Globals;
+
+    #[test]
+    fn names_share_a_slot() {
+        let mut strings = StringInterns::new();
+        let mut globals = Globals::new();
+
+        let a = globals.resolve(strings.get_or_intern("a"));
+        let b = globals.resolve(strings.get_or_intern("b"));
+        assert_ne!(a, b);
+        assert_eq!(globals.resolve(strings.get_or_intern("a")), a);
+        assert_eq!(globals.name(b).to_string(), "b");
+    }
+
+    #[test]
+    fn undefined_until_defined() {
+        let mut strings = StringInterns::new();
+        let mut globals = Globals::new();
+
+        let slot = globals.resolve(strings.get_or_intern("a"));
+        assert!(globals.get(slot).is_none());
+        assert!(globals.set(slot, Value::Nil).is_err());
+        assert!(globals.get(slot).is_none());
+
+        globals.define(slot, Value::Number(1.0));
+        assert!(globals.set(slot, Value::Number(2.0)).is_ok());
+        assert!(matches!(globals.get(slot), Some(Value::Number(n)) if *n == 2.0));
+    }
+}
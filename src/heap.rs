@@ -0,0 +1,271 @@
+use std::{cell::Cell, fmt::Display, io::Write, ops::Deref, ptr::NonNull};
+
+use crate::value::Value;
+
+// Objects that can be part of a reference cycle (closures, instances, etc) live here instead of behind an Rc,
+//   strings and functions are immutable, so can't end up in a cycle, and stay reference counted.
+pub struct Heap {
+    objects: Vec<NonNull<GcBox<dyn Trace>>>,
+    // Counts the objects and the Vecs and HashMaps they own - growth between collections is an estimate,
+    //   the exact figure is worked out again each sweep
+    bytes_allocated: usize,
+    next_gc: usize,
+}
+
+const GC_HEAP_GROW_FACTOR: usize = 2;
+const GC_INITIAL_THRESHOLD: usize = 1024 * 1024;
+
+struct GcBox<T: ?Sized> {
+    marked: Cell<bool>,
+    value: T,
+}
+
+// A handle to an object on the heap. Only valid as long as the object is reachable from the VM's roots,
+//   since anything unreachable is freed by the next collection.
+pub struct Gc<T: ?Sized> {
+    ptr: NonNull<GcBox<T>>,
+}
+
+// Objects need to tell the collector what other objects they reference
+pub trait Trace {
+    fn trace(&self, tracer: &mut Tracer);
+    // Memory the object owns outside of its own allocation, so the collector runs as that grows too
+    fn owned_bytes(&self) -> usize {
+        0
+    }
+}
+
+// Tracks the objects that have been marked but whose references haven't been traced yet (the book's gray stack)
+pub struct Tracer {
+    gray: Vec<NonNull<GcBox<dyn Trace>>>,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Heap {
+    pub fn new() -> Heap {
+        Heap {
+            objects: vec![],
+            bytes_allocated: 0,
+            next_gc: GC_INITIAL_THRESHOLD,
+        }
+    }
+    pub fn alloc<T: Trace + 'static>(&mut self, value: T) -> Gc<T> {
+        self.bytes_allocated += std::mem::size_of::<GcBox<T>>() + value.owned_bytes();
+        let boxed = Box::new(GcBox {
+            marked: Cell::new(false),
+            value,
+        });
+        let ptr = NonNull::from(Box::leak(boxed));
+        self.objects.push(ptr);
+        Gc { ptr }
+    }
+    pub fn should_collect(&self) -> bool {
+        cfg!(feature = "DEBUG_STRESS_GC") || self.bytes_allocated > self.next_gc
+    }
+    // Runs a change to an object, counting any memory the object grows by
+    pub fn grow<T: Trace + ?Sized, R>(&mut self, gc: Gc<T>, change: impl FnOnce(&T) -> R) -> R {
+        let before = gc.owned_bytes();
+        let result = change(&gc);
+        self.bytes_allocated += gc.owned_bytes().saturating_sub(before);
+        result
+    }
+    pub fn object_count(&self) -> usize {
+        self.objects.len()
+    }
+    // Frees every object that isn't reachable from the roots marked by the callback
+    //   DEBUG_LOG_GC writes what was freed to the log
+    pub fn collect<F: FnOnce(&mut Tracer)>(&mut self, log: &mut dyn Write, mark_roots: F) {
+        let (bytes_before, objects_before) = (self.bytes_allocated, self.object_count());
+        if cfg!(feature = "DEBUG_LOG_GC") {
+            let _ = writeln!(log, "-- gc begin");
+        }
+
+        let mut tracer = Tracer { gray: vec![] };
+        mark_roots(&mut tracer);
+        while let Some(object) = tracer.gray.pop() {
+            unsafe { object.as_ref() }.value.trace(&mut tracer);
+        }
+        self.sweep();
+
+        self.next_gc = (self.bytes_allocated * GC_HEAP_GROW_FACTOR).max(GC_INITIAL_THRESHOLD);
+        if cfg!(feature = "DEBUG_LOG_GC") {
+            let _ = writeln!(
+                log,
+                "-- gc end - freed {} objects, {} bytes (from {bytes_before} to {}) next at {}",
+                objects_before - self.object_count(),
+                bytes_before.saturating_sub(self.bytes_allocated),
+                self.bytes_allocated,
+                self.next_gc
+            );
+        }
+    }
+    fn sweep(&mut self) {
+        let mut kept = 0;
+        self.objects.retain(|object| {
+            let gc_box = unsafe { object.as_ref() };
+            if gc_box.marked.replace(false) {
+                kept += std::mem::size_of_val(gc_box) + gc_box.value.owned_bytes();
+                return true;
+            }
+            drop(unsafe { Box::from_raw(object.as_ptr()) });
+            false
+        });
+        self.bytes_allocated = kept;
+    }
+}
+impl Drop for Heap {
+    fn drop(&mut self) {
+        for object in self.objects.drain(..) {
+            drop(unsafe { Box::from_raw(object.as_ptr()) });
+        }
+    }
+}
+
+impl Tracer {
+    pub fn mark<T: Trace + 'static>(&mut self, gc: Gc<T>) {
+        let gc_box = unsafe { gc.ptr.as_ref() };
+        // Already marked objects have been (or will be) traced, which also stops us looping on cycles
+        if !gc_box.marked.replace(true) {
+            self.gray.push(gc.ptr);
+        }
+    }
+    pub fn mark_value(&mut self, value: &Value) {
+        match value {
+            Value::Closure(gc) => self.mark(*gc),
+            Value::Class(gc) => self.mark(*gc),
+            Value::Instance(gc) => self.mark(*gc),
+            Value::BoundMethod(gc) => self.mark(*gc),
+            Value::List(gc) => self.mark(*gc),
+            Value::Map(gc) => self.mark(*gc),
+            Value::Number(_)
+            | Value::Bool(_)
+            | Value::String(_)
+            | Value::Function(_)
+            | Value::Native(_)
+            | Value::Nil => {}
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for Gc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safe as long as the collector is only run when every live handle is reachable from a root
+        //   Handles never reach the host directly, it gets a host::Object, which is a root itself
+        unsafe { &self.ptr.as_ref().value }
+    }
+}
+// Manual impls - deriving would require T: Clone
+impl<T: ?Sized> Clone for Gc<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T: ?Sized> Copy for Gc<T> {}
+// Heap objects are only equal to themselves
+impl<T: ?Sized> PartialEq for Gc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(self.ptr.as_ptr(), other.ptr.as_ptr())
+    }
+}
+impl<T: ?Sized + Display> Display for Gc<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.deref().fmt(f)
+    }
+}
+impl<T: ?Sized + std::fmt::Debug> std::fmt::Debug for Gc<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.deref().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    struct Node {
+        next: RefCell<Option<Gc<Node>>>,
+    }
+    impl Trace for Node {
+        fn trace(&self, tracer: &mut Tracer) {
+            if let Some(next) = *self.next.borrow() {
+                tracer.mark(next);
+            }
+        }
+    }
+    fn node(heap: &mut Heap) -> Gc<Node> {
+        heap.alloc(Node {
+            next: RefCell::new(None),
+        })
+    }
+
+    #[test]
+    fn frees_unreachable_objects() {
+        let mut heap = Heap::new();
+        let root = node(&mut heap);
+        node(&mut heap);
+        assert_eq!(heap.object_count(), 2);
+
+        heap.collect(&mut std::io::sink(), |tracer| tracer.mark(root));
+        assert_eq!(heap.object_count(), 1);
+    }
+
+    #[test]
+    fn keeps_objects_reachable_through_others() {
+        let mut heap = Heap::new();
+        let root = node(&mut heap);
+        let child = node(&mut heap);
+        *root.next.borrow_mut() = Some(child);
+
+        heap.collect(&mut std::io::sink(), |tracer| tracer.mark(root));
+        assert_eq!(heap.object_count(), 2);
+        // Marks are reset, so a second collection doesn't think everything is already traced
+        heap.collect(&mut std::io::sink(), |tracer| tracer.mark(root));
+        assert_eq!(heap.object_count(), 2);
+    }
+
+    #[test]
+    fn frees_cycles() {
+        let mut heap = Heap::new();
+        let a = node(&mut heap);
+        let b = node(&mut heap);
+        *a.next.borrow_mut() = Some(b);
+        *b.next.borrow_mut() = Some(a);
+
+        heap.collect(&mut std::io::sink(), |_| {});
+        assert_eq!(heap.object_count(), 0);
+    }
+
+    struct Buffer(RefCell<Vec<u64>>);
+    impl Trace for Buffer {
+        fn trace(&self, _: &mut Tracer) {}
+        fn owned_bytes(&self) -> usize {
+            self.0.borrow().capacity() * std::mem::size_of::<u64>()
+        }
+    }
+
+    #[test]
+    fn counts_memory_objects_own() {
+        let mut heap = Heap::new();
+        let buffer = heap.alloc(Buffer(RefCell::new(vec![])));
+        assert!(cfg!(feature = "DEBUG_STRESS_GC") || !heap.should_collect());
+
+        heap.grow(buffer, |buffer| {
+            buffer.0.borrow_mut().resize(GC_INITIAL_THRESHOLD, 0)
+        });
+        assert!(heap.should_collect());
+
+        // Sweeping recounts what's left, including anything that shrank
+        buffer.0.borrow_mut().clear();
+        buffer.0.borrow_mut().shrink_to_fit();
+        heap.collect(&mut std::io::sink(), |tracer| tracer.mark(buffer));
+        assert_eq!(heap.bytes_allocated, std::mem::size_of::<GcBox<Buffer>>());
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Display,
+    rc::{Rc, Weak},
+};
+
+use crate::{
+    heap::Tracer,
+    value::{InternString, Value as VmValue},
+};
+
+// Host functions receive their arguments as a slice and either produce a value or an error message,
+// which is reported as a runtime error.
+// They can be closures, so a host function can keep its own state between calls
+pub type NativeFn = Box<dyn Fn(&[Value]) -> Result<Value, String>>;
+
+// What the host sees of a Lox value - anything that lives in the VM (lists, instances, functions, ...)
+//   is wrapped in an Object, which keeps it alive for as long as the host holds on to it
+#[derive(Clone, Debug)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(Rc<InternString>),
+    Object(Object),
+}
+
+// Note that strings made this way aren't interned - so won't compare equal to other strings - until they're
+//   handed to the VM, e.g. as the return value of a native function
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(InternString::new(value).into())
+    }
+}
+
+// Strings compare by their contents, since the host's strings aren't interned
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a.as_ref() as &str == b.as_ref() as &str,
+            (Value::Object(a), Value::Object(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Number(x) => write!(f, "{x}"),
+            Value::String(x) => write!(f, "{x}"),
+            Value::Object(x) => write!(f, "{x}"),
+        }
+    }
+}
+
+// A handle on a VM value that the collector treats as a root until the last clone is dropped
+#[derive(Clone)]
+pub struct Object(Rc<Root>);
+
+struct Root {
+    // Emptied when the VM is dropped, since its heap goes with it
+    value: RefCell<Option<VmValue>>,
+    // The VM the value belongs to, so it can't be handed to a different one
+    owner: Weak<HostRoots>,
+}
+
+impl Object {
+    // False once the VM the object came from has been dropped
+    pub fn is_alive(&self) -> bool {
+        self.0.value.borrow().is_some()
+    }
+}
+
+// Two handles are equal when they're for the same object, like `==` in Lox
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        match (&*self.0.value.borrow(), &*other.0.value.borrow()) {
+            (Some(a), Some(b)) => a == b,
+            _ => Rc::ptr_eq(&self.0, &other.0),
+        }
+    }
+}
+
+impl Display for Object {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &*self.0.value.borrow() {
+            Some(value) => write!(f, "{value}"),
+            None => write!(f, "<released object>"),
+        }
+    }
+}
+impl std::fmt::Debug for Object {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+// The objects handed out to the host, one per VM
+pub(crate) struct HostRoots {
+    roots: RefCell<Vec<Weak<Root>>>,
+    // Dropped handles leave dead entries behind, which are cleared out when the list reaches this length
+    prune_at: Cell<usize>,
+}
+
+impl HostRoots {
+    pub fn new() -> Rc<HostRoots> {
+        Rc::new(HostRoots {
+            roots: RefCell::new(vec![]),
+            prune_at: Cell::new(64),
+        })
+    }
+    pub fn export(self: &Rc<Self>, value: &VmValue) -> Value {
+        match value {
+            VmValue::Nil => Value::Nil,
+            VmValue::Bool(b) => Value::Bool(*b),
+            VmValue::Number(n) => Value::Number(*n),
+            VmValue::String(s) => Value::String(s.clone()),
+            other => {
+                let root = Rc::new(Root {
+                    value: RefCell::new(Some(other.clone())),
+                    owner: Rc::downgrade(self),
+                });
+                let mut roots = self.roots.borrow_mut();
+                if roots.len() >= self.prune_at.get() {
+                    roots.retain(|root| root.strong_count() > 0);
+                    self.prune_at.set((roots.len() * 2).max(64));
+                }
+                roots.push(Rc::downgrade(&root));
+                Value::Object(Object(root))
+            }
+        }
+    }
+    pub fn import(self: &Rc<Self>, value: Value) -> Result<VmValue, String> {
+        match value {
+            Value::Nil => Ok(VmValue::Nil),
+            Value::Bool(b) => Ok(VmValue::Bool(b)),
+            Value::Number(n) => Ok(VmValue::Number(n)),
+            Value::String(s) => Ok(VmValue::String(s)),
+            Value::Object(Object(root)) => {
+                if !Weak::ptr_eq(&root.owner, &Rc::downgrade(self)) {
+                    return Err("Can't use an object from a different VM.".to_string());
+                }
+                let value = root.value.borrow();
+                Ok(value.clone().expect("objects are alive while their VM is"))
+            }
+        }
+    }
+    // Marks everything the host still holds, forgetting the handles it has dropped
+    pub fn mark(&self, tracer: &mut Tracer) {
+        self.roots.borrow_mut().retain(|root| match root.upgrade() {
+            Some(root) => {
+                if let Some(value) = &*root.value.borrow() {
+                    tracer.mark_value(value);
+                }
+                true
+            }
+            None => false,
+        });
+    }
+}
+
+impl Drop for HostRoots {
+    fn drop(&mut self) {
+        for root in self.roots.get_mut().drain(..) {
+            if let Some(root) = root.upgrade() {
+                root.value.replace(None);
+            }
+        }
+    }
+}
@@ -10,14 +10,40 @@ pub enum Opcode {
     Jump,
     JumpIfFalse,
     Loop,
+    Call,
+    Closure,
+    CloseUpvalue,
     Print,
     Pop,
+    Stringify,
     Constant,
     DefineGlobal,
     GetGlobal,
     SetGlobal,
+    ConstantLong,
+    DefineGlobalLong,
+    GetGlobalLong,
+    SetGlobalLong,
+    ClosureLong,
+    GetPropertyLong,
+    SetPropertyLong,
+    ClassLong,
+    MethodLong,
+    GetSuperLong,
     GetLocal,
     SetLocal,
+    GetUpvalue,
+    SetUpvalue,
+    GetProperty,
+    SetProperty,
+    BuildList,
+    BuildMap,
+    GetIndex,
+    SetIndex,
+    Class,
+    Method,
+    Inherit,
+    GetSuper,
     Nil,
     True,
     False,
@@ -29,6 +55,15 @@ pub enum Opcode {
     Add,
     Subtract,
     Multiply,
+    Modulo,
+    Power,
+    FloorDivide,
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
     Divide,
     // Remember to change OPCODE_MAX if you add another one here
 }
@@ -54,7 +89,25 @@ impl From<Opcode> for u8 {
 }
 
 type ConstIdx = u8;
+// Only the low 24 bits are used, it's written as three bytes
+type LongConstIdx = u32;
+pub const LONG_CONST_MAX: usize = 1 << 24;
+// Globals are addressed by slot rather than by a name constant, with the same short and long forms
+type GlobalSlot = u8;
+type LongGlobalSlot = u32;
+pub const GLOBAL_SLOT_MAX: usize = 1 << 24;
 type StackIdx = u8;
+type ArgCount = u8;
+type UpvalueIdx = u8;
+type ItemCount = u8;
+
+// Describes where a closure captures one of its upvalues from, when the closure is created
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpvalueRef {
+    // true if it's a local of the enclosing function, false if it's one of the enclosing function's upvalues
+    pub is_local: bool,
+    pub index: u8,
+}
 // This enum exists for the sake of multi-byte instructions:
 //   Instead of `emitByte(OP_CONSTANT)` being followed by `emitByte(idx)` it's `emitOp(Op::Constant(idx))`
 //   This might turn out to be overkill
@@ -64,14 +117,44 @@ pub enum Op {
     Jump(u16),
     JumpIfFalse(u16),
     Loop(u16),
+    Call(ArgCount),
+    Closure(ConstIdx, Vec<UpvalueRef>),
+    CloseUpvalue,
     Print,
     Pop,
+    // Converts the value on top of the stack to a string, the way `print` would show it
+    Stringify,
     Constant(ConstIdx),
-    DefineGlobal(ConstIdx),
-    GetGlobal(ConstIdx),
-    SetGlobal(ConstIdx),
+    DefineGlobal(GlobalSlot),
+    GetGlobal(GlobalSlot),
+    SetGlobal(GlobalSlot),
+    ConstantLong(LongConstIdx),
+    DefineGlobalLong(LongGlobalSlot),
+    GetGlobalLong(LongGlobalSlot),
+    SetGlobalLong(LongGlobalSlot),
+    // Long forms of the instructions that take a constant, for chunks with more than 256 of them
+    ClosureLong(LongConstIdx, Vec<UpvalueRef>),
+    GetPropertyLong(LongConstIdx),
+    SetPropertyLong(LongConstIdx),
+    ClassLong(LongConstIdx),
+    MethodLong(LongConstIdx),
+    GetSuperLong(LongConstIdx),
     GetLocal(StackIdx),
     SetLocal(StackIdx),
+    GetUpvalue(UpvalueIdx),
+    SetUpvalue(UpvalueIdx),
+    GetProperty(ConstIdx),
+    SetProperty(ConstIdx),
+    // Collects the given number of values off the top of the stack into a new list
+    BuildList(ItemCount),
+    // Like BuildList, but the values are key/value pairs
+    BuildMap(ItemCount),
+    GetIndex,
+    SetIndex,
+    Class(ConstIdx),
+    Method(ConstIdx),
+    Inherit,
+    GetSuper(ConstIdx),
     Nil,
     True,
     False,
@@ -83,5 +166,44 @@ pub enum Op {
     Add,
     Subtract,
     Multiply,
+    Modulo,
+    Power,
+    FloorDivide,
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
     Divide,
 }
+
+// Picks the one byte form of the instruction if the index fits, otherwise the long form
+macro_rules! short_or_long {
+    ($fn_name: ident, $short: ident, $long: ident) => {
+        pub fn $fn_name(idx: usize) -> Op {
+            match u8::try_from(idx) {
+                Ok(short_idx) => Op::$short(short_idx),
+                Err(_) => Op::$long(idx as u32),
+            }
+        }
+    };
+}
+impl Op {
+    short_or_long!(constant, Constant, ConstantLong);
+    short_or_long!(define_global, DefineGlobal, DefineGlobalLong);
+    short_or_long!(get_global, GetGlobal, GetGlobalLong);
+    short_or_long!(set_global, SetGlobal, SetGlobalLong);
+    short_or_long!(get_property, GetProperty, GetPropertyLong);
+    short_or_long!(set_property, SetProperty, SetPropertyLong);
+    short_or_long!(class, Class, ClassLong);
+    short_or_long!(method, Method, MethodLong);
+    short_or_long!(get_super, GetSuper, GetSuperLong);
+
+    pub fn closure(idx: usize, upvalues: Vec<UpvalueRef>) -> Op {
+        match u8::try_from(idx) {
+            Ok(short_idx) => Op::Closure(short_idx, upvalues),
+            Err(_) => Op::ClosureLong(idx as u32, upvalues),
+        }
+    }
+}
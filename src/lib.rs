@@ -1,6 +1,14 @@
 mod chunk;
 mod compiler;
+pub mod diagnostic;
+mod globals;
+mod heap;
+pub mod host;
 mod instructions;
 mod scanner;
 mod value;
 pub mod vm;
+
+pub use host::{NativeFn, Object, Value};
+pub use scanner::Span;
+pub use value::InternString;
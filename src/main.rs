@@ -1,24 +1,37 @@
 use std::{
     fs,
-    io::{self, stdin, stdout, Write},
+    io::{self, stderr, stdin, stdout, IsTerminal, Write},
     process::exit,
 };
 
-use rlox::vm::{InterpretError, VM};
+use rlox::{
+    diagnostic::DiagnosticFormat,
+    vm::{InterpretError, VM},
+};
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() == 1 {
-        repl().unwrap_or_else(|_| exit(64))
-    } else if args.len() == 2 {
-        run_file(&args[1]);
-    } else {
-        eprintln!("Usage: rlox [path]");
-        exit(64);
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    // `--plain` keeps errors to the book's one line format, which the example tests compare against
+    let format = match args.iter().position(|arg| arg == "--plain") {
+        Some(idx) => {
+            args.remove(idx);
+            DiagnosticFormat::Plain
+        }
+        None => DiagnosticFormat::Rich {
+            colour: stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+        },
+    };
+    match args.as_slice() {
+        [] => repl(format).unwrap_or_else(|_| exit(64)),
+        [path] => run_file(path, format),
+        _ => {
+            eprintln!("Usage: rlox [--plain] [path]");
+            exit(64);
+        }
     }
 }
 
-fn repl() -> io::Result<()> {
+fn repl(format: DiagnosticFormat) -> io::Result<()> {
     let mut vm = VM::new();
     loop {
         print!("> ");
@@ -28,23 +41,38 @@ fn repl() -> io::Result<()> {
         if buf == "\n" {
             break;
         }
-        let _ = vm.interpret(buf);
-        // Clean up between lines, right now just cleans the string intern map a bit
+        if let Err(err) = vm.interpret(buf) {
+            report(&err, format);
+        }
+        // Clean up between lines, frees unreachable objects and cleans the string intern map
         vm.garbage_collect();
     }
     Ok(())
 }
 
-fn run_file(path: &str) {
+fn run_file(path: &str, format: DiagnosticFormat) {
     let source = fs::read_to_string(path).unwrap_or_else(|_| {
         println!("Could not read file \"{path}\".");
         exit(74)
     });
     let mut vm = VM::new();
-    match vm.interpret(source) {
-        Err(InterpretError::CompileError) => exit(65),
-        Err(InterpretError::RuntimeError) => exit(70),
-        Ok(()) => {}
+    if let Err(err) = vm.interpret(source) {
+        report(&err, format);
+        match err {
+            InterpretError::CompileError(_) => exit(65),
+            InterpretError::RuntimeError(_) => exit(70),
+        }
+    }
+}
+
+fn report(err: &InterpretError, format: DiagnosticFormat) {
+    match err {
+        InterpretError::CompileError(diagnostics) => {
+            for diagnostic in diagnostics {
+                eprintln!("{}", diagnostic.render(format));
+            }
+        }
+        InterpretError::RuntimeError(err) => eprintln!("{}", err.render(format)),
     }
 }
 
@@ -1,12 +1,30 @@
+mod escapes;
 mod identifier_identifier;
+mod numbers;
 mod token_kind;
+pub use escapes::unescape;
+pub use numbers::parse_number;
+use std::rc::Rc;
 pub use token_kind::TokenKind;
+use unicode_ident::{is_xid_continue, is_xid_start};
 
 #[derive(Debug, Clone)]
 pub struct Token {
     pub kind: TokenKind,
     pub lexeme: String,
+    // The line the token ends on, which is where errors are reported
     pub line: usize,
+    pub span: Span,
+}
+
+// Where a piece of source starts and ends - byte offsets for slicing the source,
+//   plus the line and column (1-based, counted in chars) it starts at for showing to people
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
 }
 
 impl From<TokenKind> for u8 {
@@ -16,62 +34,129 @@ impl From<TokenKind> for u8 {
 }
 
 pub struct Scanner {
-    source: String,
+    source: Rc<str>,
     line: usize,
     start: usize,
     current: usize,
+    // The line the current token started on, which differs from `line` for strings with newlines in them
+    start_line: usize,
+    // 1-based, counted in chars - kept up to date as we go, rather than counting back from the offset for every token
+    column: usize,
+    start_column: usize,
+    // One entry per string interpolation we're inside, counting the braces opened within its expression
+    //   so we know which `}` goes back to scanning the string
+    interpolations: Vec<usize>,
 }
 
 #[derive(Clone)]
 pub struct ScanErr {
-    pub line: usize,
+    pub span: Span,
     pub msg: String,
 }
 type ScanResult = Result<Token, ScanErr>;
 impl Scanner {
     pub fn new(source: String) -> Self {
         Self {
-            source,
+            source: Rc::from(source),
             line: 1,
             start: 0,
             current: 0,
+            start_line: 1,
+            column: 1,
+            start_column: 1,
+            interpolations: vec![],
         }
     }
 
+    pub fn source(&self) -> &Rc<str> {
+        &self.source
+    }
+
     // char-aware slice of source
     fn slice(&self, start: usize, end: usize) -> &str {
         &self.source[start..end]
     }
 
+    // The span of the current token
+    fn span(&self) -> Span {
+        Span {
+            start: self.start,
+            end: self.current,
+            line: self.start_line,
+            column: self.start_column,
+        }
+    }
+
     fn make_token(&self, kind: TokenKind) -> ScanResult {
         Ok(Token {
             kind,
             lexeme: self.slice(self.start, self.current).to_string(),
             line: self.line,
+            span: self.span(),
         })
     }
     fn make_error(&self, msg: &str) -> ScanResult {
-        Err(ScanErr {
+        Err(self.scan_err(msg))
+    }
+    fn scan_err(&self, msg: &str) -> ScanErr {
+        ScanErr {
             msg: msg.to_string(),
-            line: self.line,
-        })
+            span: self.span(),
+        }
     }
 
     pub fn scan_token(&mut self) -> ScanResult {
         self.skip_whitespace();
         self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.column;
         match self.advance() {
             Some('(') => self.make_token(TokenKind::LeftParen),
             Some(')') => self.make_token(TokenKind::RightParen),
-            Some('{') => self.make_token(TokenKind::LeftBrace),
-            Some('}') => self.make_token(TokenKind::RightBrace),
+            Some('{') => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.make_token(TokenKind::LeftBrace)
+            }
+            Some('}') => match self.interpolations.last_mut() {
+                Some(0) => {
+                    self.interpolations.pop();
+                    self.string()
+                }
+                Some(depth) => {
+                    *depth -= 1;
+                    self.make_token(TokenKind::RightBrace)
+                }
+                None => self.make_token(TokenKind::RightBrace),
+            },
+            Some('[') => self.make_token(TokenKind::LeftBracket),
+            Some(']') => self.make_token(TokenKind::RightBracket),
             Some(';') => self.make_token(TokenKind::Semicolon),
             Some(',') => self.make_token(TokenKind::Comma),
+            Some(':') => self.make_token(TokenKind::Colon),
             Some('.') => self.make_token(TokenKind::Dot),
             Some('-') => self.make_token(TokenKind::Minus),
             Some('+') => self.make_token(TokenKind::Plus),
             Some('/') => self.make_token(TokenKind::Slash),
-            Some('*') => self.make_token(TokenKind::Star),
+            Some('*') => {
+                if self.try_match('*') {
+                    self.make_token(TokenKind::StarStar)
+                } else {
+                    self.make_token(TokenKind::Star)
+                }
+            }
+            Some('%') => self.make_token(TokenKind::Percent),
+            Some('&') => self.make_token(TokenKind::Ampersand),
+            Some('|') => self.make_token(TokenKind::Pipe),
+            Some('^') => self.make_token(TokenKind::Caret),
+            Some('~') => {
+                if self.try_match('/') {
+                    self.make_token(TokenKind::TildeSlash)
+                } else {
+                    self.make_token(TokenKind::Tilde)
+                }
+            }
             Some('!') => {
                 if self.try_match('=') {
                     self.make_token(TokenKind::BangEqual)
@@ -89,6 +174,8 @@ impl Scanner {
             Some('<') => {
                 if self.try_match('=') {
                     self.make_token(TokenKind::LessEqual)
+                } else if self.try_match('<') {
+                    self.make_token(TokenKind::LessLess)
                 } else {
                     self.make_token(TokenKind::Less)
                 }
@@ -96,13 +183,15 @@ impl Scanner {
             Some('>') => {
                 if self.try_match('=') {
                     self.make_token(TokenKind::GreaterEqual)
+                } else if self.try_match('>') {
+                    self.make_token(TokenKind::GreaterGreater)
                 } else {
                     self.make_token(TokenKind::Greater)
                 }
             }
             Some('"') => self.string(),
             Some('0'..='9') => self.number(),
-            Some('a'..='z' | 'A'..='Z') => self.identifier(),
+            Some(c) if c == '_' || is_xid_start(c) => self.identifier(),
             Some(_) => self.make_error("Unexpected character."),
             None => self.make_token(TokenKind::Eof),
         }
@@ -110,36 +199,68 @@ impl Scanner {
 
     // Extended scanning logic for complex cases
 
+    // Scans up to the end of the string, or the start of an interpolated expression
+    //   Starts just after the opening quote, or the `}` closing the previous interpolation
     fn string(&mut self) -> ScanResult {
-        loop {
+        let (kind, end_len) = loop {
             match self.advance() {
-                None => return self.make_error("Unterminated string."),
-                Some('"') => return self.make_token(TokenKind::String),
-                Some('\n') => self.line += 1,
+                // Reported where the source runs out, since that's where the closing quote is missing
+                None => {
+                    return Err(ScanErr {
+                        span: Span {
+                            start: self.current,
+                            end: self.current,
+                            line: self.line,
+                            column: self.column,
+                        },
+                        msg: "Unterminated string.".to_string(),
+                    });
+                }
+                Some('"') => break (TokenKind::String, 1),
+                Some('$') if self.try_match('{') => {
+                    self.interpolations.push(0);
+                    break (TokenKind::Interpolation, 2);
+                }
+                Some('\n') => self.newline(),
+                // Skip whatever is escaped, so an escaped quote doesn't end the string - the escape is checked below
+                Some('\\') => {
+                    if self.advance() == Some('\n') {
+                        self.newline();
+                    }
+                }
                 Some(_) => {}
             }
+        };
+        let contents = self.slice(self.start + 1, self.current - end_len);
+        if let Err((offset, msg)) = unescape(contents) {
+            // Point at the bad escape itself, rather than where the string ends
+            let before = &contents[..offset];
+            let line = self.start_line + before.matches('\n').count();
+            let column = match before.rfind('\n') {
+                Some(idx) => before[idx + 1..].chars().count() + 1,
+                // Past the opening quote or `}`
+                None => self.start_column + 1 + before.chars().count(),
+            };
+            let start = self.start + 1 + offset;
+            let escape_len = contents[offset..]
+                .chars()
+                .nth(1)
+                .map_or(1, |c| 1 + c.len_utf8());
+            let span = Span {
+                start,
+                end: start + escape_len,
+                line,
+                column,
+            };
+            return Err(ScanErr { span, msg });
         }
+        self.make_token(kind)
     }
     fn is_digit(opt: Option<char>) -> bool {
         opt.is_some_and(|x| x.is_ascii_digit())
     }
-    fn number(&mut self) -> ScanResult {
-        while Self::is_digit(self.peek()) {
-            self.advance();
-        }
-        // Fractional part
-        if matches!(self.peek(), Some('.')) && Self::is_digit(self.peek_next()) {
-            // Consume the "."
-            self.advance();
-            while Self::is_digit(self.peek()) {
-                self.advance();
-            }
-        }
-
-        self.make_token(TokenKind::Number)
-    }
     fn identifier(&mut self) -> ScanResult {
-        while self.peek().is_some_and(|x| x.is_ascii_alphanumeric()) {
+        while self.peek().is_some_and(is_xid_continue) {
             self.advance();
         }
         let id_type = self.identifier_type();
@@ -154,8 +275,8 @@ impl Scanner {
                     self.advance();
                 }
                 (Some('\n'), _) => {
-                    self.line += 1;
                     self.advance();
+                    self.newline();
                 }
                 (Some('/'), Some('/')) => loop {
                     if let None | Some('\n') = self.peek() {
@@ -175,13 +296,91 @@ impl Scanner {
         self.source[self.current..].chars().nth(1)
     }
     fn advance(&mut self) -> Option<char> {
-        self.peek().inspect(|c| self.current += c.len_utf8())
+        self.peek().inspect(|c| {
+            self.current += c.len_utf8();
+            self.column += 1;
+        })
     }
     fn try_match(&mut self, expected: char) -> bool {
         let res = self.peek() == Some(expected);
         if res {
-            self.current += expected.len_utf8();
+            self.advance();
         }
         res
     }
+    // Called just after consuming a newline
+    fn newline(&mut self) {
+        self.line += 1;
+        self.column = 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(source: &str) -> Vec<Span> {
+        let mut scanner = Scanner::new(source.to_string());
+        let mut spans = vec![];
+        loop {
+            let token = scanner.scan_token().ok().unwrap();
+            if token.kind == TokenKind::Eof {
+                return spans;
+            }
+            spans.push(token.span);
+        }
+    }
+
+    #[test]
+    fn token_spans() {
+        let source = "var abc = 12;\n  print \"é\" + abc;";
+        let spans = spans(source);
+        let slices: Vec<_> = spans.iter().map(|s| &source[s.start..s.end]).collect();
+        assert_eq!(
+            slices,
+            ["var", "abc", "=", "12", ";", "print", "\"é\"", "+", "abc", ";"]
+        );
+        let positions: Vec<_> = spans.iter().map(|s| (s.line, s.column)).collect();
+        assert_eq!(
+            positions,
+            [
+                (1, 1),
+                (1, 5),
+                (1, 9),
+                (1, 11),
+                (1, 13),
+                (2, 3),
+                (2, 9),
+                (2, 13),
+                (2, 15),
+                (2, 18)
+            ]
+        );
+    }
+
+    #[test]
+    fn multiline_string_starts_on_its_first_line() {
+        let mut scanner = Scanner::new("  \"a\nb\"".to_string());
+        let token = scanner.scan_token().ok().unwrap();
+        assert_eq!(token.line, 2);
+        assert_eq!((token.span.line, token.span.column), (1, 3));
+    }
+
+    #[test]
+    fn error_spans() {
+        let mut scanner = Scanner::new("x\n  \"ab\\q\"".to_string());
+        scanner.scan_token().ok().unwrap();
+        let err = scanner.scan_token().err().unwrap();
+        assert_eq!(err.msg, r"Unknown escape sequence '\q'.");
+        assert_eq!((err.span.start, err.span.end), (7, 9));
+        assert_eq!((err.span.line, err.span.column), (2, 6));
+
+        let mut scanner = Scanner::new("\"ab\ncd".to_string());
+        let err = scanner.scan_token().err().unwrap();
+        assert_eq!((err.span.line, err.span.column), (2, 3));
+
+        let mut scanner = Scanner::new("\"ab\n é\\q\"".to_string());
+        let err = scanner.scan_token().err().unwrap();
+        assert_eq!((err.span.line, err.span.column), (2, 3));
+    }
 }
@@ -0,0 +1,87 @@
+use std::str::CharIndices;
+
+// Decodes the escape sequences in the contents of a string literal (without its quotes)
+//   On failure, gives the byte offset of the bad escape within the contents, for working out its line
+pub fn unescape(contents: &str) -> Result<String, (usize, String)> {
+    let mut unescaped = String::with_capacity(contents.len());
+    let mut chars = contents.char_indices();
+    while let Some((idx, c)) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        unescaped.push(match chars.next() {
+            Some((_, 'n')) => '\n',
+            Some((_, 't')) => '\t',
+            Some((_, 'r')) => '\r',
+            Some((_, '\\')) => '\\',
+            Some((_, '"')) => '"',
+            // Only needed to write a literal `${` without starting an interpolation
+            Some((_, '$')) => '$',
+            Some((_, '0')) => '\0',
+            Some((_, 'u')) => unicode_escape(&mut chars)
+                .ok_or_else(|| (idx, "Invalid unicode escape sequence.".to_string()))?,
+            Some((_, other)) => {
+                return Err((idx, format!("Unknown escape sequence '\\{other}'.")));
+            }
+            // The scanner won't end a string on an escaped quote, so there's always something after the backslash
+            None => unreachable!("string should not end in a backslash"),
+        });
+    }
+    Ok(unescaped)
+}
+
+// The `{XXXX}` part of `\u{XXXX}`, one to six hex digits naming a unicode scalar value
+fn unicode_escape(chars: &mut CharIndices) -> Option<char> {
+    if chars.next()?.1 != '{' {
+        return None;
+    }
+    let mut code: u32 = 0;
+    let mut digits = 0;
+    loop {
+        let (_, c) = chars.next()?;
+        if c == '}' {
+            break;
+        }
+        code = code * 16 + c.to_digit(16)?;
+        digits += 1;
+        if digits > 6 {
+            return None;
+        }
+    }
+    if digits == 0 {
+        return None;
+    }
+    char::from_u32(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unescape;
+
+    #[test]
+    fn simple_escapes() {
+        assert_eq!(
+            unescape(r#"a\nb\tc\rd\\e\"f\0\${}"#).unwrap(),
+            "a\nb\tc\rd\\e\"f\0${}"
+        );
+    }
+
+    #[test]
+    fn unicode_escapes() {
+        assert_eq!(unescape(r"\u{41}\u{e9}\u{1F600}").unwrap(), "Aé😀");
+        assert!(unescape(r"\u{}").is_err());
+        assert!(unescape(r"\u41").is_err());
+        assert!(unescape(r"\u{1234567}").is_err());
+        // Surrogates aren't valid chars
+        assert!(unescape(r"\u{D800}").is_err());
+    }
+
+    #[test]
+    fn unknown_escape() {
+        assert_eq!(
+            unescape(r"ab\q").unwrap_err(),
+            (2, r"Unknown escape sequence '\q'.".to_string())
+        );
+    }
+}
@@ -11,29 +11,40 @@ fn match_rest(expected: &'static str, actual: &str, skip: usize, if_match: Token
 impl Scanner {
     pub(super) fn identifier_type(&mut self) -> TokenKind {
         let word = self.slice(self.start, self.current);
+        // Compared byte-wise, since identifiers can start with multi-byte characters that can't be sliced in half
+        //   The keywords are all ascii, so a byte of a multi-byte character never matches one
+        let bytes = word.as_bytes();
         // The idea is to be efficient, only checking a first letter match, rather than, e.g. looking up in a hash-table, which might be more expensive.
         // TBH, I'm not sure this is significantly more efficient than just doing a bunch of == comparisons; may want to benchmark it, but this is (roughly) the approach the book took
         macro_rules! simple_match {
             ($match:literal, $kind:ident) => {
-                // Doing slicing indexing here because we know we're only matching ascii here
-                //   don't need unicode aware splitting
-                if (&word[0..1] == &$match[0..1]) {
+                if bytes[0] == $match.as_bytes()[0] {
                     return match_rest(&$match[1..], word, 1, TokenKind::$kind);
                 }
             };
         }
         simple_match!("if", If);
         simple_match!("and", And);
-        simple_match!("class", Class);
+        simple_match!("break", Break);
+        if bytes[0] == b'c' {
+            if word.len() < 5 {
+                return TokenKind::Identifier;
+            }
+            return match bytes[1] {
+                b'l' => match_rest("ass", word, 2, TokenKind::Class),
+                b'o' => match_rest("ntinue", word, 2, TokenKind::Continue),
+                _ => TokenKind::Identifier,
+            };
+        }
         simple_match!("else", Else);
-        if &word[..1] == "f" {
+        if bytes[0] == b'f' {
             if word.len() < 3 {
                 return TokenKind::Identifier;
             }
-            return match &word[1..2] {
-                "a" => match_rest("lse", word, 2, TokenKind::False),
-                "o" => match_rest("r", word, 2, TokenKind::For),
-                "u" => match_rest("n", word, 2, TokenKind::Fun),
+            return match bytes[1] {
+                b'a' => match_rest("lse", word, 2, TokenKind::False),
+                b'o' => match_rest("r", word, 2, TokenKind::For),
+                b'u' => match_rest("n", word, 2, TokenKind::Fun),
                 _ => TokenKind::Identifier,
             };
         }
@@ -41,13 +52,14 @@ impl Scanner {
         simple_match!("or", Or);
         simple_match!("print", Print);
         simple_match!("return", Return);
-        if &word[0..1] == "t" {
+        simple_match!("super", Super);
+        if bytes[0] == b't' {
             if word.len() < 4 {
                 return TokenKind::Identifier;
             }
-            return match &word[1..2] {
-                "h" => match_rest("is", word, 2, TokenKind::This),
-                "r" => match_rest("ue", word, 2, TokenKind::True),
+            return match bytes[1] {
+                b'h' => match_rest("is", word, 2, TokenKind::This),
+                b'r' => match_rest("ue", word, 2, TokenKind::True),
                 _ => TokenKind::Identifier,
             };
         }
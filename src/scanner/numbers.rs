@@ -0,0 +1,139 @@
+use unicode_ident::is_xid_continue;
+
+use super::{ScanErr, ScanResult, Scanner, TokenKind};
+
+impl Scanner {
+    // The first digit has already been consumed
+    pub(super) fn number(&mut self) -> ScanResult {
+        if self.slice(self.start, self.current) == "0" {
+            let prefixed = match self.peek() {
+                Some('x') => Some((16, "hex")),
+                Some('b') => Some((2, "binary")),
+                Some('o') => Some((8, "octal")),
+                _ => None,
+            };
+            if let Some((radix, name)) = prefixed {
+                self.advance();
+                if !self.peek().is_some_and(|c| c.is_digit(radix)) {
+                    return self.make_error(&format!("Expect digits in {name} literal."));
+                }
+                self.digits(radix, false)?;
+                return self.finish_number();
+            }
+        }
+
+        self.digits(10, true)?;
+        // Fractional part
+        if matches!(self.peek(), Some('.')) && Self::is_digit(self.peek_next()) {
+            // Consume the "."
+            self.advance();
+            self.digits(10, false)?;
+        }
+        // Exponent
+        if matches!(self.peek(), Some('e' | 'E')) {
+            self.advance();
+            if matches!(self.peek(), Some('+' | '-')) {
+                self.advance();
+            }
+            if !Self::is_digit(self.peek()) {
+                return self.make_error("Expect digits in exponent.");
+            }
+            self.digits(10, false)?;
+        }
+        self.finish_number()
+    }
+    // Consumes a run of digits, which can be split up with single underscores, e.g. 1_000_000
+    fn digits(&mut self, radix: u32, mut after_digit: bool) -> Result<(), ScanErr> {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_digit(radix) => {
+                    self.advance();
+                    after_digit = true;
+                }
+                Some('_') => {
+                    self.advance();
+                    if !after_digit || !self.peek().is_some_and(|c| c.is_digit(radix)) {
+                        return Err(self.scan_err("Digit separators must be between digits."));
+                    }
+                    after_digit = false;
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+    fn finish_number(&mut self) -> ScanResult {
+        // Something like 0b102 or 12abc - swallow the rest of it, so it's reported as one error
+        if self.peek().is_some_and(is_xid_continue) {
+            while self.peek().is_some_and(is_xid_continue) {
+                self.advance();
+            }
+            return self.make_error("Malformed number literal.");
+        }
+        self.make_token(TokenKind::Number)
+    }
+}
+
+// Converts the lexeme of a number token into its value - the scanner has already checked the syntax
+pub fn parse_number(lexeme: &str) -> Option<f64> {
+    let cleaned = lexeme.replace('_', "");
+    let (radix, digits) = match cleaned.get(..2) {
+        Some("0x") => (16, &cleaned[2..]),
+        Some("0b") => (2, &cleaned[2..]),
+        Some("0o") => (8, &cleaned[2..]),
+        _ => return cleaned.parse().ok(),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    // Accumulating as a float means huge literals lose precision instead of overflowing
+    digits.chars().try_fold(0.0, |acc, c| {
+        c.to_digit(radix)
+            .map(|digit| acc * radix as f64 + digit as f64)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_number;
+    use crate::scanner::{Scanner, TokenKind};
+
+    fn scan(source: &str) -> Result<String, String> {
+        let mut scanner = Scanner::new(source.to_string());
+        match scanner.scan_token() {
+            Ok(token) if token.kind == TokenKind::Number => Ok(token.lexeme),
+            Ok(token) => Err(format!("not a number: {:?}", token.kind)),
+            Err(err) => Err(err.msg),
+        }
+    }
+
+    #[test]
+    fn parses_literals() {
+        assert_eq!(parse_number("0xFF"), Some(255.0));
+        assert_eq!(parse_number("0b1010"), Some(10.0));
+        assert_eq!(parse_number("0o17"), Some(15.0));
+        assert_eq!(parse_number("1.5e-3"), Some(0.0015));
+        assert_eq!(parse_number("2E3"), Some(2000.0));
+        assert_eq!(parse_number("1_000_000"), Some(1_000_000.0));
+        assert_eq!(parse_number("0xdead_beef"), Some(3735928559.0));
+    }
+
+    #[test]
+    fn scans_whole_literal() {
+        assert_eq!(scan("1_000.000_1e+1_0;").unwrap(), "1_000.000_1e+1_0");
+        assert_eq!(scan("0x1F)").unwrap(), "0x1F");
+        // The dot isn't part of the number unless digits follow it
+        assert_eq!(scan("1.foo").unwrap(), "1");
+    }
+
+    #[test]
+    fn rejects_malformed_literals() {
+        assert_eq!(scan("0x").unwrap_err(), "Expect digits in hex literal.");
+        assert_eq!(scan("0b102").unwrap_err(), "Malformed number literal.");
+        assert_eq!(scan("12abc").unwrap_err(), "Malformed number literal.");
+        assert_eq!(scan("1e").unwrap_err(), "Expect digits in exponent.");
+        assert_eq!(scan("1e+").unwrap_err(), "Expect digits in exponent.");
+        for separators in ["1__0", "10_", "0x_1", "1_.5", "1e_5"] {
+            assert!(scan(separators).is_err(), "{separators} should be rejected");
+        }
+    }
+}
@@ -6,6 +6,9 @@ pub enum TokenKind {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -13,7 +16,15 @@ pub enum TokenKind {
     Semicolon,
     Slash,
     Star,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
     // One or two character tokens.
+    Tilde,
+    StarStar,
+    // Integer division can't be `//`, that's a comment
+    TildeSlash,
     Bang,
     BangEqual,
     Equal,
@@ -22,13 +33,19 @@ pub enum TokenKind {
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
     // Literals.
     Identifier,
     String,
+    // The part of an interpolated string before a `${`, the rest of the string comes after the embedded expression
+    Interpolation,
     Number,
     // Keywords.
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     For,
@@ -1,6 +1,20 @@
+mod class;
+mod closure;
+mod function;
+mod list;
+mod map;
+mod native;
 mod string_intern;
 
-use std::{fmt::Display, rc::Rc};
+use crate::heap::Gc;
+pub use class::{BoundMethod, Class, Instance};
+pub use closure::{Closure, Upvalue};
+pub use function::Function;
+pub use list::List;
+pub use map::{Map, MapKey};
+pub(crate) use native::{BuiltinFn, HeapNativeFn};
+pub use native::{Native, NativeFunction};
+use std::{cell::RefCell, fmt::Display, rc::Rc};
 pub use string_intern::{InternString, StringInterns};
 
 #[derive(Clone, PartialEq, Debug)]
@@ -8,6 +22,14 @@ pub enum Value {
     Number(f64),
     Bool(bool),
     String(Rc<InternString>),
+    Function(Rc<Function>),
+    Closure(Gc<Closure>),
+    Class(Gc<RefCell<Class>>),
+    Instance(Gc<RefCell<Instance>>),
+    BoundMethod(Gc<BoundMethod>),
+    Native(Rc<Native>),
+    List(Gc<RefCell<List>>),
+    Map(Gc<RefCell<Map>>),
     Nil,
 }
 
@@ -24,6 +46,14 @@ impl Display for Value {
             Value::Bool(b) => write!(f, "{b}"),
             Value::Number(x) => write!(f, "{x}"),
             Value::String(x) => write!(f, "{x}"),
+            Value::Function(x) => write!(f, "{x}"),
+            Value::Closure(x) => write!(f, "{x}"),
+            Value::Class(x) => write!(f, "{}", x.borrow()),
+            Value::Instance(x) => write!(f, "{}", x.borrow()),
+            Value::BoundMethod(x) => write!(f, "{x}"),
+            Value::Native(x) => write!(f, "{x}"),
+            Value::List(x) => write!(f, "{}", x.borrow()),
+            Value::Map(x) => write!(f, "{}", x.borrow()),
         }
     }
 }
@@ -0,0 +1,98 @@
+use super::*;
+use crate::heap::{Gc, Trace, Tracer};
+use std::{cell::RefCell, collections::HashMap};
+
+pub struct Class {
+    pub name: Rc<InternString>,
+    pub methods: HashMap<Rc<InternString>, Gc<Closure>>,
+}
+
+pub struct Instance {
+    pub class: Gc<RefCell<Class>>,
+    pub fields: HashMap<Rc<InternString>, Value>,
+}
+
+// A method that's been accessed off of an instance, which remembers the instance to use as `this`
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Gc<Closure>,
+}
+
+impl Class {
+    pub fn new(name: Rc<InternString>) -> Class {
+        Class {
+            name,
+            methods: HashMap::new(),
+        }
+    }
+}
+
+impl Instance {
+    pub fn new(class: Gc<RefCell<Class>>) -> Instance {
+        Instance {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+}
+
+impl Trace for RefCell<Class> {
+    fn trace(&self, tracer: &mut Tracer) {
+        for method in self.borrow().methods.values() {
+            tracer.mark(*method);
+        }
+    }
+    fn owned_bytes(&self) -> usize {
+        self.borrow().methods.capacity() * std::mem::size_of::<(Rc<InternString>, Gc<Closure>)>()
+    }
+}
+impl Trace for RefCell<Instance> {
+    fn trace(&self, tracer: &mut Tracer) {
+        let instance = self.borrow();
+        tracer.mark(instance.class);
+        for value in instance.fields.values() {
+            tracer.mark_value(value);
+        }
+    }
+    fn owned_bytes(&self) -> usize {
+        self.borrow().fields.capacity() * std::mem::size_of::<(Rc<InternString>, Value)>()
+    }
+}
+impl Trace for BoundMethod {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.mark_value(&self.receiver);
+        tracer.mark(self.method);
+    }
+}
+
+impl Display for Class {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+impl Display for Instance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} instance", self.class.borrow().name)
+    }
+}
+impl Display for BoundMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.method.fmt(f)
+    }
+}
+
+impl std::fmt::Debug for Class {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
+impl std::fmt::Debug for Instance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
+impl std::fmt::Debug for BoundMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
@@ -0,0 +1,57 @@
+use super::*;
+use crate::heap::{Gc, Trace, Tracer};
+use std::cell::RefCell;
+
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Gc<RefCell<Upvalue>>>,
+}
+
+// A variable captured by a closure
+#[derive(Debug)]
+pub enum Upvalue {
+    // The variable is still live on the value stack, at this index
+    Open(usize),
+    // The variable has gone out of scope, so the upvalue owns it now
+    Closed(Value),
+}
+
+impl Closure {
+    pub fn new(function: Rc<Function>) -> Closure {
+        Closure {
+            upvalues: Vec::with_capacity(function.upvalue_count),
+            function,
+        }
+    }
+}
+
+impl Trace for Closure {
+    fn trace(&self, tracer: &mut Tracer) {
+        // Functions can't reference heap objects, so only the upvalues need tracing
+        for upvalue in &self.upvalues {
+            tracer.mark(*upvalue);
+        }
+    }
+    fn owned_bytes(&self) -> usize {
+        self.upvalues.capacity() * std::mem::size_of::<Gc<RefCell<Upvalue>>>()
+    }
+}
+impl Trace for RefCell<Upvalue> {
+    fn trace(&self, tracer: &mut Tracer) {
+        // Open upvalues point at the stack, which is already a root
+        if let Upvalue::Closed(value) = &*self.borrow() {
+            tracer.mark_value(value);
+        }
+    }
+}
+
+impl Display for Closure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.function.fmt(f)
+    }
+}
+impl std::fmt::Debug for Closure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
@@ -0,0 +1,43 @@
+use super::*;
+use crate::chunk::Chunk;
+
+pub struct Function {
+    pub arity: u8,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+    // None for the top-level script
+    pub name: Option<Rc<InternString>>,
+}
+
+impl Function {
+    pub fn new(name: Option<Rc<InternString>>) -> Function {
+        Function {
+            arity: 0,
+            upvalue_count: 0,
+            chunk: Chunk::new(),
+            name,
+        }
+    }
+}
+
+// Functions are only equal to themselves, same as the book's pointer comparison of objects
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "<fn {name}>"),
+            None => write!(f, "<script>"),
+        }
+    }
+}
+// Manual impl so we don't dump the whole chunk when debug printing a Value
+impl std::fmt::Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
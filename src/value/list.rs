@@ -0,0 +1,54 @@
+use super::*;
+use crate::heap::{Trace, Tracer};
+use std::cell::{Cell, RefCell};
+
+pub struct List {
+    pub items: Vec<Value>,
+    // Set while the list is being displayed, so a list that contains itself doesn't print forever
+    displaying: Cell<bool>,
+}
+
+impl List {
+    pub fn new(items: Vec<Value>) -> List {
+        List {
+            items,
+            displaying: Cell::new(false),
+        }
+    }
+}
+
+impl Trace for RefCell<List> {
+    fn trace(&self, tracer: &mut Tracer) {
+        for value in &self.borrow().items {
+            tracer.mark_value(value);
+        }
+    }
+    fn owned_bytes(&self) -> usize {
+        self.borrow().items.capacity() * std::mem::size_of::<Value>()
+    }
+}
+
+impl Display for List {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.displaying.replace(true) {
+            return write!(f, "[...]");
+        }
+        let result = (|| {
+            write!(f, "[")?;
+            for (i, item) in self.items.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{item}")?;
+            }
+            write!(f, "]")
+        })();
+        self.displaying.set(false);
+        result
+    }
+}
+impl std::fmt::Debug for List {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
@@ -0,0 +1,148 @@
+use super::*;
+use crate::heap::{Trace, Tracer};
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::HashMap,
+    hash::Hash,
+};
+
+pub struct Map {
+    pub entries: HashMap<MapKey, Value>,
+    // Set while the map is being displayed, so a map that contains itself doesn't print forever
+    displaying: Cell<bool>,
+}
+
+// The values that can be used as map keys - the ones that compare by value, plus strings, which are interned
+#[derive(Clone, Debug)]
+pub enum MapKey {
+    Nil,
+    Bool(bool),
+    // Never NaN, and never -0, so numbers that compare equal hash the same
+    Number(f64),
+    String(Rc<InternString>),
+}
+
+impl MapKey {
+    pub fn from_value(value: &Value) -> Result<MapKey, &'static str> {
+        match value {
+            Value::Nil => Ok(MapKey::Nil),
+            Value::Bool(b) => Ok(MapKey::Bool(*b)),
+            Value::Number(n) if n.is_nan() => Err("Map key can't be NaN."),
+            // Adding zero turns -0 into 0
+            Value::Number(n) => Ok(MapKey::Number(n + 0.0)),
+            Value::String(s) => Ok(MapKey::String(s.clone())),
+            _ => Err("Map key must be a string, number, bool or nil."),
+        }
+    }
+    pub fn to_value(&self) -> Value {
+        match self {
+            MapKey::Nil => Value::Nil,
+            MapKey::Bool(b) => Value::Bool(*b),
+            MapKey::Number(n) => Value::Number(*n),
+            MapKey::String(s) => Value::String(s.clone()),
+        }
+    }
+    // Keys of different types sort by type first
+    fn rank(&self) -> u8 {
+        match self {
+            MapKey::Nil => 0,
+            MapKey::Bool(_) => 1,
+            MapKey::Number(_) => 2,
+            MapKey::String(_) => 3,
+        }
+    }
+}
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (MapKey::Nil, MapKey::Nil) => true,
+            (MapKey::Bool(a), MapKey::Bool(b)) => a == b,
+            (MapKey::Number(a), MapKey::Number(b)) => a == b,
+            (MapKey::String(a), MapKey::String(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+impl Eq for MapKey {}
+impl Hash for MapKey {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+        match self {
+            MapKey::Nil => {}
+            MapKey::Bool(b) => b.hash(state),
+            MapKey::Number(n) => n.to_bits().hash(state),
+            MapKey::String(s) => s.hash(state),
+        }
+    }
+}
+// Only used to give maps a predictable order when they're listed or displayed,
+//   strings sort by their contents rather than by address
+impl Ord for MapKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (MapKey::Bool(a), MapKey::Bool(b)) => a.cmp(b),
+            (MapKey::Number(a), MapKey::Number(b)) => a.total_cmp(b),
+            (MapKey::String(a), MapKey::String(b)) => (**a).cmp(&**b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+impl PartialOrd for MapKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Map {
+    pub fn new(entries: HashMap<MapKey, Value>) -> Map {
+        Map {
+            entries,
+            displaying: Cell::new(false),
+        }
+    }
+    // The entries ordered by key, since the hash map's own order isn't stable
+    pub fn sorted_entries(&self) -> Vec<(&MapKey, &Value)> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by_key(|(key, _)| *key);
+        entries
+    }
+}
+
+impl Trace for RefCell<Map> {
+    fn trace(&self, tracer: &mut Tracer) {
+        // Keys can't be heap objects, so only the values need tracing
+        for value in self.borrow().entries.values() {
+            tracer.mark_value(value);
+        }
+    }
+    fn owned_bytes(&self) -> usize {
+        self.borrow().entries.capacity() * std::mem::size_of::<(MapKey, Value)>()
+    }
+}
+
+impl Display for Map {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.displaying.replace(true) {
+            return write!(f, "{{...}}");
+        }
+        let result = (|| {
+            write!(f, "{{")?;
+            for (i, (key, value)) in self.sorted_entries().into_iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}: {value}", key.to_value())?;
+            }
+            write!(f, "}}")
+        })();
+        self.displaying.set(false);
+        result
+    }
+}
+impl std::fmt::Debug for Map {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
@@ -0,0 +1,36 @@
+use super::*;
+use crate::{heap::Heap, host::NativeFn};
+
+// Builtins work on the VM's own values, rather than the host's handles
+pub(crate) type BuiltinFn = fn(&[Value]) -> Result<Value, String>;
+// Some builtins need to create heap objects, e.g. the list returned by `keys`
+pub(crate) type HeapNativeFn = fn(&mut Heap, &[Value]) -> Result<Value, String>;
+
+pub struct Native {
+    pub name: Rc<InternString>,
+    pub arity: u8,
+    pub function: NativeFunction,
+}
+
+pub enum NativeFunction {
+    Host(NativeFn),
+    Builtin(BuiltinFn),
+    Heap(HeapNativeFn),
+}
+
+impl PartialEq for Native {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Display for Native {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+impl std::fmt::Debug for Native {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
@@ -1,6 +1,7 @@
 use super::*;
 use std::{
     collections::HashMap,
+    hash::Hash,
     ops::{Deref, DerefMut},
     rc::{Rc, Weak},
 };
@@ -20,6 +21,12 @@ impl PartialEq for InternString {
     }
 }
 impl Eq for InternString {}
+// Hashes the address rather than the contents, to match the reference equality
+impl Hash for InternString {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::ptr::hash(self.0.as_ptr(), state)
+    }
+}
 
 impl Deref for InternString {
     type Target = str;
@@ -1,80 +1,173 @@
-use std::{collections::HashMap, rc::Rc};
+mod natives;
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, Write},
+    rc::Rc,
+};
 
 use crate::{
-    chunk::Chunk,
     compiler,
+    diagnostic::{Diagnostic, DiagnosticKind, RuntimeError, StackFrame},
+    globals::Globals,
+    heap::{Gc, Heap},
+    host::{self, HostRoots, NativeFn},
     instructions::Opcode,
-    value::{InternString, StringInterns, Value},
+    scanner::Span,
+    value::{
+        BoundMethod, BuiltinFn, Class, Closure, HeapNativeFn, Instance, InternString, List, Map,
+        MapKey, Native, NativeFunction, StringInterns, Upvalue, Value,
+    },
 };
 
-const STACK_MAX: usize = 256;
+// Default limit on the number of values on the stack, can be changed with `set_stack_limit`
+// There's no separate limit on call depth - every call takes at least one slot, for the function being called
+const STACK_MAX: usize = 16384;
 
 pub struct VM {
-    // The book uses raw pointers, this is an index because I think I'd have to jump into unsafe to make that work
-    ip: usize,
+    frames: Vec<CallFrame>,
     values: ValueStack,
     strings: StringInterns,
-    // TODO - see if we can leverage interning
-    globals: HashMap<String, Value>,
+    globals: Globals,
+    // Upvalues still pointing at the stack, ordered by the stack slot they point to
+    open_upvalues: Vec<Gc<RefCell<Upvalue>>>,
+    // Kept around to look up initializers without interning "init" on every class call
+    init_string: Rc<InternString>,
+    heap: Heap,
+    // Objects the host has been handed, which stay alive until it drops them
+    host_roots: Rc<HostRoots>,
+    // Where `print` writes - stdout unless the host says otherwise
+    output: Box<dyn Write>,
+    // Where the DEBUG_ features write their tracing, kept apart so it doesn't get mixed into the script's output
+    debug_output: Box<dyn Write>,
+}
+
+struct CallFrame {
+    closure: Gc<Closure>,
+    // The book uses raw pointers, this is an index because I think I'd have to jump into unsafe to make that work
+    ip: usize,
+    // Index in the value stack of the frame's slot zero, local slots are relative to this
+    slot_base: usize,
 }
 
+// Nothing is printed by the VM, it's up to the host how to show these
 #[derive(Debug)]
 pub enum InterpretError {
-    CompileError,
-    RuntimeError,
+    // Every error found in the source, in order
+    CompileError(Vec<Diagnostic>),
+    // Boxed to keep results small, since every push returns one
+    RuntimeError(Box<RuntimeError>),
 }
 type InterpretResult = Result<(), InterpretError>;
 
 // ValueStack, only $5 at burger king with fries
+// Grows as needed, up to a limit, to catch runaway scripts before they eat all the host's memory
 struct ValueStack {
-    values: [Option<Value>; STACK_MAX],
-    stack_top: usize,
+    values: Vec<Value>,
+    max: usize,
 }
+// Returned when pushing would go past the stack's limit
+struct StackOverflow;
 impl ValueStack {
-    pub fn new() -> ValueStack {
+    pub fn new(max: usize) -> ValueStack {
         ValueStack {
-            values: [const { None }; STACK_MAX],
-            stack_top: 0,
+            values: Vec::with_capacity(256.min(max)),
+            max,
         }
     }
-    pub fn push(&mut self, value: Value) {
-        self.values[self.stack_top] = Some(value);
-        self.stack_top += 1;
+    pub fn push(&mut self, value: Value) -> Result<(), StackOverflow> {
+        if self.values.len() >= self.max {
+            return Err(StackOverflow);
+        }
+        self.values.push(value);
+        Ok(())
     }
     pub fn pop(&mut self) -> Value {
-        debug_assert!(
-            self.stack_top > 0,
-            "Attempted to pop from empty value stack"
-        );
-        self.stack_top -= 1;
-        let val = self.values[self.stack_top].take();
-        val.expect("stack should not be empty")
+        self.values.pop().expect("stack should not be empty")
     }
     pub fn peek(&mut self) -> &Value {
-        let val = self.values[self.stack_top - 1].as_ref();
-        val.expect("stack should not be empty")
+        self.values.last().expect("stack should not be empty")
     }
-    pub fn peek_at(&mut self, from_top: usize) -> &mut Value {
-        if from_top >= self.stack_top {
+    pub fn peek_at(&self, from_top: usize) -> &Value {
+        if from_top >= self.values.len() {
             panic!(
                 "Peeked too deep - {from_top} - only had {} values",
-                self.stack_top
+                self.values.len()
             )
         }
-        let val = self.values[self.stack_top - from_top - 1].as_mut();
-        val.expect("stack not have empty values in it")
+        &self.values[self.values.len() - from_top - 1]
+    }
+    // Unlike peek_at, these index from the bottom of the stack
+    pub fn get(&self, idx: usize) -> &Value {
+        &self.values[idx]
+    }
+    pub fn get_mut(&mut self, idx: usize) -> &mut Value {
+        &mut self.values[idx]
     }
-    pub fn debug(&self) {
-        print!("[ ");
-        for i in 0..self.stack_top {
-            let val = self.values[i].as_ref().expect("stack should not be empty");
+    // The top `count` values, e.g. the arguments to a call
+    pub fn top(&self, count: usize) -> &[Value] {
+        &self.values[self.values.len() - count..]
+    }
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+    // Drops everything at or above the given index
+    pub fn truncate(&mut self, len: usize) {
+        self.values.truncate(len);
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &Value> {
+        self.values.iter()
+    }
+    pub fn debug(&self, out: &mut dyn Write) -> io::Result<()> {
+        write!(out, "[ ")?;
+        for val in &self.values {
             if let Value::String(str) = val {
-                print!("'{str}' ")
+                write!(out, "'{str}' ")?;
             } else {
-                print!("{val} ")
+                write!(out, "{val} ")?;
             }
         }
-        println!("]");
+        writeln!(out, "]")
+    }
+}
+
+fn as_integer(value: &Value) -> Option<i64> {
+    match *value {
+        // Anything outside the i64 range would be saturated by the cast rather than exact
+        Value::Number(n) if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 => {
+            Some(n as i64)
+        }
+        _ => None,
+    }
+}
+fn shift_amount(amount: i64) -> Result<u32, &'static str> {
+    match u32::try_from(amount) {
+        Ok(amount) if amount < i64::BITS => Ok(amount),
+        _ => Err("Shift amount must be between 0 and 63."),
+    }
+}
+
+// Checks the value can be used to index into the list, giving the error message if not
+fn list_index(list: &List, index: &Value) -> Result<usize, &'static str> {
+    let Value::Number(index) = *index else {
+        return Err("List index must be a number.");
+    };
+    if index.fract() != 0.0 {
+        return Err("List index must be an integer.");
+    }
+    if index < 0.0 || index >= list.items.len() as f64 {
+        return Err("List index out of range.");
+    }
+    Ok(index as usize)
+}
+
+// Used where only a string constant could be - e.g. property names
+fn expect_string(val: Value) -> Rc<InternString> {
+    if let Value::String(val) = val {
+        val
+    } else {
+        panic!("Got non-string constant")
     }
 }
 
@@ -85,53 +178,290 @@ impl Default for VM {
 }
 impl VM {
     pub fn new() -> VM {
-        VM {
-            ip: 0,
-            values: ValueStack::new(),
-            // Shared between the VM (for strings defined at runtime)
-            // and the compiler, for constants
-            strings: StringInterns::new(),
-            globals: HashMap::new(),
-        }
+        // Shared between the VM (for strings defined at runtime)
+        // and the compiler, for constants
+        let mut strings = StringInterns::new();
+        let mut vm = VM {
+            frames: vec![],
+            values: ValueStack::new(STACK_MAX),
+            init_string: strings.get_or_intern("init"),
+            strings,
+            globals: Globals::new(),
+            open_upvalues: vec![],
+            heap: Heap::new(),
+            host_roots: HostRoots::new(),
+            output: Box::new(io::stdout()),
+            debug_output: Box::new(io::stdout()),
+        };
+        vm.define_builtin_natives();
+        vm
+    }
+    // The most values the stack can hold before a script fails with a stack overflow - this also limits how deep calls can go
+    pub fn set_stack_limit(&mut self, max_values: usize) {
+        self.values.max = max_values;
+    }
+    // Sends the output of `print` somewhere other than stdout
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Box::new(output);
+    }
+    pub fn set_debug_output(&mut self, debug_output: impl Write + 'static) {
+        self.debug_output = Box::new(debug_output);
+    }
+    // Makes a host function available to scripts as a global
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: u8,
+        function: impl Fn(&[host::Value]) -> Result<host::Value, String> + 'static,
+    ) {
+        let function: NativeFn = Box::new(function);
+        self.define_native_function(name, arity, NativeFunction::Host(function));
+    }
+    fn define_builtin_native(&mut self, name: &str, arity: u8, function: BuiltinFn) {
+        self.define_native_function(name, arity, NativeFunction::Builtin(function));
+    }
+    fn define_heap_native(&mut self, name: &str, arity: u8, function: HeapNativeFn) {
+        self.define_native_function(name, arity, NativeFunction::Heap(function));
+    }
+    fn define_native_function(&mut self, name: &str, arity: u8, function: NativeFunction) {
+        let native = Native {
+            name: self.strings.get_or_intern(name),
+            arity,
+            function,
+        };
+        let slot = self.globals.resolve(native.name.clone());
+        self.globals.define(slot, Value::Native(Rc::new(native)));
     }
     pub fn new_and_run(source: String) -> InterpretResult {
         let mut vm = VM::new();
         vm.interpret(source)
     }
     pub fn interpret(&mut self, source: String) -> InterpretResult {
-        let Some(chunk) = compiler::compile(source, &mut self.strings) else {
-            return Err(InterpretError::CompileError);
-        };
-        self.ip = 0;
-        self.run(&chunk)
+        let function = compiler::compile(
+            source,
+            &mut self.strings,
+            &mut self.globals,
+            &mut *self.debug_output,
+        )
+        .map_err(InterpretError::CompileError)?;
+        let closure = self.heap.alloc(Closure::new(Rc::new(function)));
+        self.push(Value::Closure(closure))?;
+        self.call(closure, 0)?;
+        self.run()
     }
 
-    fn read_byte(&mut self, chunk: &Chunk) -> u8 {
-        let val = chunk.code[self.ip];
-        self.ip += 1;
+    fn push(&mut self, value: Value) -> InterpretResult {
+        match self.values.push(value) {
+            Ok(()) => Ok(()),
+            Err(StackOverflow) => self.runtime_err("Stack overflow."),
+        }
+    }
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("should have a call frame")
+    }
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("should have a call frame")
+    }
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame_mut();
+        let val = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
         val
     }
-    fn read_constant<'a>(&mut self, chunk: &'a Chunk) -> &'a Value {
-        chunk.get_constant_unwrap(self.read_byte(chunk))
+    fn read_u16(&mut self) -> u16 {
+        u16::from_be_bytes([self.read_byte(), self.read_byte()])
+    }
+    fn read_u24(&mut self) -> usize {
+        u32::from_be_bytes([0, self.read_byte(), self.read_byte(), self.read_byte()]) as usize
+    }
+    fn get_constant(&self, const_idx: usize) -> Value {
+        self.frame()
+            .closure
+            .function
+            .chunk
+            .get_constant_unwrap(const_idx)
+            .clone()
+    }
+    fn read_constant(&mut self) -> Value {
+        let const_idx = self.read_byte() as usize;
+        self.get_constant(const_idx)
+    }
+    fn read_constant_long(&mut self) -> Value {
+        let const_idx = self.read_u24();
+        self.get_constant(const_idx)
+    }
+    fn runtime_err(&mut self, msg: &str) -> InterpretResult {
+        self.report_runtime_err(msg, None)
+    }
+    // Only the rich diagnostic format shows the hint
+    fn runtime_err_with_hint(&mut self, msg: &str, hint: &str) -> InterpretResult {
+        self.report_runtime_err(msg, Some(hint))
+    }
+    fn report_runtime_err(&mut self, msg: &str, hint: Option<&str>) -> InterpretResult {
+        // Points at the instruction that failed in the innermost frame - there may not be one if the script never started
+        let diagnostic = match self.frames.last() {
+            Some(frame) => {
+                // The ip has already moved past the instruction that failed
+                let chunk = &frame.closure.function.chunk;
+                let offset = frame.ip - 1;
+                Diagnostic::new(
+                    DiagnosticKind::RuntimeError,
+                    msg,
+                    chunk.lines[offset],
+                    chunk.spans[offset],
+                    &chunk.source,
+                )
+            }
+            None => Diagnostic::new(DiagnosticKind::RuntimeError, msg, 0, Span::default(), ""),
+        };
+        let diagnostic = match hint {
+            Some(hint) => diagnostic.with_hint(hint),
+            None => diagnostic,
+        };
+        let stack_trace = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| StackFrame {
+                function: frame
+                    .closure
+                    .function
+                    .name
+                    .as_ref()
+                    .map(|name| name.to_string()),
+                line: frame.closure.function.chunk.lines[frame.ip - 1],
+            })
+            .collect();
+        self.reset_stack();
+        Err(InterpretError::RuntimeError(Box::new(RuntimeError {
+            diagnostic,
+            stack_trace,
+        })))
+    }
+    fn reset_stack(&mut self) {
+        // Closures that escaped (into a global, say) can outlive the error, so they keep the values they captured
+        self.close_upvalues(0);
+        self.values.truncate(0);
+        self.frames.clear();
     }
-    // This is used in places where only a string could be - e.g. variable names
-    fn read_string_constant<'a>(&mut self, chunk: &'a Chunk) -> &'a Rc<InternString> {
-        if let Value::String(val) = self.read_constant(chunk) {
-            val
-        } else {
-            panic!("Got non-string constant")
+
+    fn call_value(&mut self, arg_count: u8) -> InterpretResult {
+        let callee_slot = self.values.len() - arg_count as usize - 1;
+        match self.values.get(callee_slot).clone() {
+            Value::Closure(closure) => self.call(closure, arg_count),
+            Value::BoundMethod(bound) => {
+                // The receiver takes the callee's place in slot zero, to be used as `this`
+                *self.values.get_mut(callee_slot) = bound.receiver.clone();
+                self.call(bound.method, arg_count)
+            }
+            Value::Class(class) => {
+                let instance = self.heap.alloc(RefCell::new(Instance::new(class)));
+                *self.values.get_mut(callee_slot) = Value::Instance(instance);
+                let initializer = class.borrow().methods.get(&self.init_string).cloned();
+                match initializer {
+                    Some(initializer) => self.call(initializer, arg_count),
+                    None if arg_count != 0 => {
+                        self.runtime_err(&format!("Expected 0 arguments but got {arg_count}."))
+                    }
+                    None => Ok(()),
+                }
+            }
+            Value::Native(native) => self.call_native(&native, arg_count),
+            _ => self.runtime_err("Can only call functions and classes."),
         }
     }
-    fn runtime_err(&self, msg: &str, chunk: &Chunk) -> InterpretResult {
-        let line = chunk.lines[self.ip];
-        eprintln!("{msg}\n[line {line}] in script");
-        Err(InterpretError::RuntimeError)
+    fn call_native(&mut self, native: &Native, arg_count: u8) -> InterpretResult {
+        if arg_count != native.arity {
+            return self.runtime_err(&format!(
+                "Expected {} arguments but got {arg_count}.",
+                native.arity
+            ));
+        }
+        let callee_slot = self.values.len() - arg_count as usize - 1;
+        let args = self.values.top(arg_count as usize);
+        let result = match &native.function {
+            // The host gets handles that keep any objects alive, in case it holds on to them
+            NativeFunction::Host(function) => {
+                let args: Vec<_> = args.iter().map(|arg| self.host_roots.export(arg)).collect();
+                function(&args).and_then(|result| self.host_roots.import(result))
+            }
+            NativeFunction::Builtin(function) => function(args),
+            NativeFunction::Heap(function) => function(&mut self.heap, args),
+        };
+        match result {
+            Ok(result) => {
+                // Natives can build strings without access to the interner, so intern them on the way in
+                let result = match result {
+                    Value::String(s) => self.strings.build_string_value(&s),
+                    other => other,
+                };
+                self.values.truncate(callee_slot);
+                self.push(result)
+            }
+            Err(msg) => self.runtime_err(&msg),
+        }
     }
-    pub fn run(&mut self, chunk: &Chunk) -> InterpretResult {
-        macro_rules! push {
-            ($expression:expr) => {
-                self.values.push($expression)
+    // Pushes the method bound to the instance on top of the stack, in place of the instance
+    fn bind_method(
+        &mut self,
+        class: &Gc<RefCell<Class>>,
+        name: &Rc<InternString>,
+    ) -> InterpretResult {
+        let Some(method) = class.borrow().methods.get(name).cloned() else {
+            return self.runtime_err(&format!("Undefined property '{name}'."));
+        };
+        let receiver = self.values.pop();
+        let bound = self.heap.alloc(BoundMethod { receiver, method });
+        self.push(Value::BoundMethod(bound))
+    }
+    fn call(&mut self, closure: Gc<Closure>, arg_count: u8) -> InterpretResult {
+        if arg_count != closure.function.arity {
+            return self.runtime_err(&format!(
+                "Expected {} arguments but got {arg_count}.",
+                closure.function.arity
+            ));
+        }
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            // The function itself is beneath the arguments, in slot zero
+            slot_base: self.values.len() - arg_count as usize - 1,
+        });
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Gc<RefCell<Upvalue>> {
+        // Multiple closures capturing the same variable need to share the upvalue
+        let position = self.open_upvalues.partition_point(
+            |upvalue| matches!(*upvalue.borrow(), Upvalue::Open(open_slot) if open_slot < slot),
+        );
+        if let Some(existing) = self.open_upvalues.get(position) {
+            if matches!(*existing.borrow(), Upvalue::Open(open_slot) if open_slot == slot) {
+                return *existing;
+            }
+        }
+        let upvalue = self.heap.alloc(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.insert(position, upvalue);
+        upvalue
+    }
+    // Moves every variable at or above the slot off the stack and into the upvalues that captured them
+    fn close_upvalues(&mut self, from_slot: usize) {
+        while let Some(upvalue) = self.open_upvalues.last() {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) if slot >= from_slot => slot,
+                _ => break,
             };
+            *upvalue.borrow_mut() = Upvalue::Closed(self.values.get(slot).clone());
+            self.open_upvalues.pop();
+        }
+    }
+
+    fn run(&mut self) -> InterpretResult {
+        macro_rules! push {
+            ($expression:expr) => {{
+                let value = $expression;
+                self.push(value)?
+            }};
         }
         macro_rules! pop {
             () => {
@@ -145,13 +475,147 @@ impl VM {
         }
         macro_rules! runtime_err {
             ($msg: expr) => {
-                return self.runtime_err($msg, chunk)
+                return self.runtime_err($msg)
+            };
+            ($msg: expr, hint: $hint: expr) => {
+                return self.runtime_err_with_hint($msg, $hint)
             };
         }
+        // The global instructions come in short and long forms, which only differ in how they read the slot
+        macro_rules! define_global {
+            ($slot: expr) => {{
+                let slot = $slot;
+                // book does peek() here, too
+                let val = pop!();
+                self.globals.define(slot, val);
+            }};
+        }
+        macro_rules! get_global {
+            ($slot: expr) => {{
+                let slot = $slot;
+                match self.globals.get(slot) {
+                    Some(val) => push!(val.clone()),
+                    None => {
+                        let var_name = self.globals.name(slot).clone();
+                        runtime_err!(
+                            &format!("Undefined variable '{var_name}'."),
+                            hint: &format!("Declare it with 'var {var_name}' before using it.")
+                        )
+                    }
+                }
+            }};
+        }
+        macro_rules! set_global {
+            ($slot: expr) => {{
+                let slot = $slot;
+                let val = peek!().clone();
+                if self.globals.set(slot, val).is_err() {
+                    let var_name = self.globals.name(slot).clone();
+                    runtime_err!(
+                        &format!("Undefined variable '{var_name}'."),
+                        hint: &format!("Declare it with 'var {var_name}' before assigning to it.")
+                    )
+                }
+            }};
+        }
+        // So do the instructions that take a constant, which only differ in how they read it
+        macro_rules! closure {
+            ($function: expr) => {{
+                let Value::Function(function) = $function else {
+                    panic!("Got non-function constant for closure")
+                };
+                let mut closure = Closure::new(function);
+                for _ in 0..closure.function.upvalue_count {
+                    let is_local = self.read_byte() == 1;
+                    let index = self.read_byte() as usize;
+                    closure.upvalues.push(if is_local {
+                        let slot = self.frame().slot_base + index;
+                        self.capture_upvalue(slot)
+                    } else {
+                        self.frame().closure.upvalues[index]
+                    });
+                }
+                let closure = self.heap.alloc(closure);
+                push!(Value::Closure(closure));
+            }};
+        }
+        macro_rules! class {
+            ($name: expr) => {{
+                let name = expect_string($name);
+                let class = self.heap.alloc(RefCell::new(Class::new(name)));
+                push!(Value::Class(class));
+            }};
+        }
+        macro_rules! method {
+            ($name: expr) => {{
+                let name = expect_string($name);
+                let Value::Closure(method) = pop!() else {
+                    panic!("Method should be a closure")
+                };
+                let &Value::Class(class) = peek!() else {
+                    panic!("Methods should be defined on a class")
+                };
+                self.heap.grow(class, |class| {
+                    class.borrow_mut().methods.insert(name, method)
+                });
+            }};
+        }
+        macro_rules! get_super {
+            ($name: expr) => {{
+                let name = expect_string($name);
+                let Value::Class(superclass) = pop!() else {
+                    panic!("super should be a class")
+                };
+                self.bind_method(&superclass, &name)?;
+            }};
+        }
+        macro_rules! get_property {
+            ($name: expr) => {{
+                let name = expect_string($name);
+                let Value::Instance(instance) = peek!().clone() else {
+                    runtime_err!("Only instances have properties.");
+                };
+                // Fields shadow methods
+                let field = instance.borrow().fields.get(&name).cloned();
+                match field {
+                    Some(val) => {
+                        pop!();
+                        push!(val);
+                    }
+                    None => self.bind_method(&instance.borrow().class, &name)?,
+                }
+            }};
+        }
+        macro_rules! set_property {
+            ($name: expr) => {{
+                let name = expect_string($name);
+                let Value::Instance(instance) = self.values.peek_at(1).clone() else {
+                    runtime_err!("Only instances have fields.");
+                };
+                let val = pop!();
+                self.heap.grow(instance, |instance| {
+                    instance.borrow_mut().fields.insert(name, val.clone())
+                });
+                // Pop the instance, leaving the assigned value as the result of the expression
+                pop!();
+                push!(val);
+            }};
+        }
         loop {
+            // Only collect between instructions - any object still in use is reachable from the stack or globals at this point,
+            //   rather than in some Rust local variable where the collector can't see it
+            if self.heap.should_collect() {
+                self.garbage_collect();
+            }
             if cfg!(feature = "DEBUG_TRACE_EXECUTION") {
-                self.values.debug();
-                chunk.disassemble_instruction(self.ip);
+                // Tracing is best effort, a failed write shouldn't stop the script
+                let _ = self.values.debug(&mut *self.debug_output);
+                let frame = self.frames.last().expect("should have a call frame");
+                frame
+                    .closure
+                    .function
+                    .chunk
+                    .disassemble_instruction(frame.ip, &mut *self.debug_output);
             }
             // Using a macro, allows returning from outer function
             macro_rules! binary_op {
@@ -164,75 +628,201 @@ impl VM {
                     }
                 };
             }
-            match self.read_byte(chunk).try_into() {
+            // The bitwise operators work on numbers that are exact integers, as 64 bit ints
+            macro_rules! integer_op {
+                ($op: expr) => {
+                    match (as_integer(&pop!()), as_integer(&pop!())) {
+                        (Some(b), Some(a)) => match $op(a, b) {
+                            Ok(result) => push!(Value::Number(result as f64)),
+                            Err(msg) => runtime_err!(msg),
+                        },
+                        _ => runtime_err!("Operands must be integers."),
+                    }
+                };
+            }
+            match self.read_byte().try_into() {
                 Ok(Opcode::Return) => {
-                    return Ok(());
+                    let result = pop!();
+                    let frame = self.frames.pop().expect("should have a call frame");
+                    self.close_upvalues(frame.slot_base);
+                    // Discard the callee and its arguments and locals
+                    self.values.truncate(frame.slot_base);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    push!(result);
                 }
                 Ok(Opcode::Jump) => {
-                    self.ip +=
-                        u16::from_be_bytes([self.read_byte(chunk), self.read_byte(chunk)]) as usize;
+                    let dist = self.read_u16();
+                    self.frame_mut().ip += dist as usize;
                 }
                 Ok(Opcode::JumpIfFalse) => {
-                    let dist = u16::from_be_bytes([self.read_byte(chunk), self.read_byte(chunk)]);
+                    let dist = self.read_u16();
                     let val = peek!();
                     if val.is_falsey() {
-                        self.ip += dist as usize;
+                        self.frame_mut().ip += dist as usize;
                     }
                 }
                 Ok(Opcode::Loop) => {
-                    let go_back =
-                        u16::from_be_bytes([self.read_byte(chunk), self.read_byte(chunk)]) as usize;
+                    let go_back = self.read_u16() as usize;
                     // Need to jump past the Loop operation itself
-                    self.ip -= go_back + 3;
+                    self.frame_mut().ip -= go_back + 3;
                 }
-                Ok(Opcode::Constant) => {
-                    let val = self.read_constant(chunk);
-                    push!(val.clone());
+                Ok(Opcode::Call) => {
+                    let arg_count = self.read_byte();
+                    self.call_value(arg_count)?;
                 }
-                Ok(Opcode::DefineGlobal) => {
-                    let var_name = self.read_string_constant(chunk);
-                    // book does peek() here, too
-                    let val = pop!();
-                    self.globals.insert(var_name.to_string(), val);
+                Ok(Opcode::Closure) => closure!(self.read_constant()),
+                Ok(Opcode::ClosureLong) => closure!(self.read_constant_long()),
+                Ok(Opcode::CloseUpvalue) => {
+                    self.close_upvalues(self.values.len() - 1);
+                    pop!();
                 }
-                Ok(Opcode::GetGlobal) => {
-                    let var_name = self.read_string_constant(chunk);
-                    match self.globals.get(&var_name.to_string()) {
-                        Some(val) => push!(val.clone()),
-                        None => {
-                            runtime_err!(&format!("Undefined variable '{var_name}'."))
-                        }
-                    }
+                Ok(Opcode::Constant) => {
+                    let val = self.read_constant();
+                    push!(val);
                 }
-                Ok(Opcode::SetGlobal) => {
-                    let var_name = self.read_string_constant(chunk);
-                    let val = peek!();
-
-                    if self
-                        .globals
-                        .insert(var_name.to_string(), val.clone())
-                        .is_none()
-                    {
-                        self.globals.remove(&var_name.to_string());
-                        runtime_err!(&format!("Undefined variable '{var_name}'."))
-                    }
+                Ok(Opcode::ConstantLong) => {
+                    let val = self.read_constant_long();
+                    push!(val);
                 }
+                Ok(Opcode::DefineGlobal) => define_global!(self.read_byte() as usize),
+                Ok(Opcode::DefineGlobalLong) => define_global!(self.read_u24()),
+                Ok(Opcode::GetGlobal) => get_global!(self.read_byte() as usize),
+                Ok(Opcode::GetGlobalLong) => get_global!(self.read_u24()),
+                Ok(Opcode::SetGlobal) => set_global!(self.read_byte() as usize),
+                Ok(Opcode::SetGlobalLong) => set_global!(self.read_u24()),
                 Ok(Opcode::GetLocal) => {
-                    let idx = self.read_byte(chunk);
-                    let val = self.values.peek_at(idx as usize).clone();
+                    let slot = self.frame().slot_base + self.read_byte() as usize;
+                    let val = self.values.get(slot).clone();
                     push!(val)
                 }
                 Ok(Opcode::SetLocal) => {
-                    let idx = self.read_byte(chunk);
+                    let slot = self.frame().slot_base + self.read_byte() as usize;
                     // Leave the value there there since assignment evaluates to the assigned value
-                    let val = peek!();
-                    // +1 to account for the peeked value still being on the stack
-                    *self.values.peek_at(idx as usize + 1) = val.clone();
+                    let val = peek!().clone();
+                    *self.values.get_mut(slot) = val;
+                }
+                Ok(Opcode::GetUpvalue) => {
+                    let idx = self.read_byte() as usize;
+                    let val = match &*self.frame().closure.upvalues[idx].borrow() {
+                        Upvalue::Open(slot) => self.values.get(*slot).clone(),
+                        Upvalue::Closed(val) => val.clone(),
+                    };
+                    push!(val)
+                }
+                Ok(Opcode::SetUpvalue) => {
+                    let idx = self.read_byte() as usize;
+                    let val = peek!().clone();
+                    let upvalue = self.frame().closure.upvalues[idx];
+                    match &mut *upvalue.borrow_mut() {
+                        Upvalue::Open(slot) => *self.values.get_mut(*slot) = val,
+                        Upvalue::Closed(closed) => *closed = val,
+                    };
+                }
+                Ok(Opcode::Class) => class!(self.read_constant()),
+                Ok(Opcode::ClassLong) => class!(self.read_constant_long()),
+                Ok(Opcode::Method) => method!(self.read_constant()),
+                Ok(Opcode::MethodLong) => method!(self.read_constant_long()),
+                Ok(Opcode::Inherit) => {
+                    let Value::Class(superclass) = self.values.peek_at(1).clone() else {
+                        runtime_err!("Superclass must be a class.");
+                    };
+                    let Value::Class(subclass) = pop!() else {
+                        panic!("Only classes should inherit")
+                    };
+                    // Copy the methods down - the subclass's own methods are added afterwards, overriding these
+                    let methods = superclass.borrow().methods.clone();
+                    self.heap.grow(subclass, |subclass| {
+                        subclass.borrow_mut().methods.extend(methods)
+                    });
+                }
+                Ok(Opcode::GetSuper) => get_super!(self.read_constant()),
+                Ok(Opcode::GetSuperLong) => get_super!(self.read_constant_long()),
+                Ok(Opcode::GetProperty) => get_property!(self.read_constant()),
+                Ok(Opcode::GetPropertyLong) => get_property!(self.read_constant_long()),
+                Ok(Opcode::SetProperty) => set_property!(self.read_constant()),
+                Ok(Opcode::SetPropertyLong) => set_property!(self.read_constant_long()),
+                Ok(Opcode::BuildList) => {
+                    let item_count = self.read_byte() as usize;
+                    let items = self.values.top(item_count).to_vec();
+                    self.values.truncate(self.values.len() - item_count);
+                    let list = self.heap.alloc(RefCell::new(List::new(items)));
+                    push!(Value::List(list));
+                }
+                Ok(Opcode::BuildMap) => {
+                    let entry_count = self.read_byte() as usize;
+                    let mut entries = HashMap::with_capacity(entry_count);
+                    for pair in self.values.top(entry_count * 2).chunks(2) {
+                        let key = match MapKey::from_value(&pair[0]) {
+                            Ok(key) => key,
+                            Err(msg) => runtime_err!(msg),
+                        };
+                        entries.insert(key, pair[1].clone());
+                    }
+                    self.values.truncate(self.values.len() - entry_count * 2);
+                    let map = self.heap.alloc(RefCell::new(Map::new(entries)));
+                    push!(Value::Map(map));
+                }
+                Ok(Opcode::GetIndex) => {
+                    let index = pop!();
+                    let val = match pop!() {
+                        Value::List(list) => match list_index(&list.borrow(), &index) {
+                            Ok(idx) => list.borrow().items[idx].clone(),
+                            Err(msg) => runtime_err!(msg),
+                        },
+                        Value::Map(map) => {
+                            let key = match MapKey::from_value(&index) {
+                                Ok(key) => key,
+                                Err(msg) => runtime_err!(msg),
+                            };
+                            let val = map.borrow().entries.get(&key).cloned();
+                            match val {
+                                Some(val) => val,
+                                None => runtime_err!(&format!("Undefined key '{index}'.")),
+                            }
+                        }
+                        _ => runtime_err!("Only lists and maps can be indexed."),
+                    };
+                    push!(val);
+                }
+                Ok(Opcode::SetIndex) => {
+                    let val = pop!();
+                    let index = pop!();
+                    match pop!() {
+                        Value::List(list) => {
+                            let idx = list_index(&list.borrow(), &index);
+                            match idx {
+                                Ok(idx) => list.borrow_mut().items[idx] = val.clone(),
+                                Err(msg) => runtime_err!(msg),
+                            }
+                        }
+                        Value::Map(map) => match MapKey::from_value(&index) {
+                            Ok(key) => {
+                                self.heap.grow(map, |map| {
+                                    map.borrow_mut().entries.insert(key, val.clone())
+                                });
+                            }
+                            Err(msg) => runtime_err!(msg),
+                        },
+                        _ => runtime_err!("Only lists and maps can be indexed."),
+                    }
+                    // Like other assignments, this evaluates to the assigned value
+                    push!(val);
                 }
                 Ok(Opcode::Pop) => {
                     pop!();
                 }
-                Ok(Opcode::Print) => println!("{}", pop!()),
+                Ok(Opcode::Print) => {
+                    let val = pop!();
+                    if writeln!(self.output, "{val}").is_err() {
+                        runtime_err!("Could not write output.");
+                    }
+                }
+                Ok(Opcode::Stringify) => match pop!() {
+                    Value::String(s) => push!(Value::String(s)),
+                    other => push!(self.strings.build_string_value(&other.to_string())),
+                },
                 Ok(Opcode::True) => push!(Value::Bool(true)),
                 Ok(Opcode::False) => push!(Value::Bool(false)),
                 Ok(Opcode::Nil) => push!(Value::Nil),
@@ -257,19 +847,212 @@ impl VM {
                     (Value::String(v2), Value::String(v1)) => {
                         push!(self.strings.build_string_value(&format!("{v1}{v2}")))
                     }
-                    _ => runtime_err!("Operands must be two numbers or two strings."),
+                    _ => runtime_err!(
+                        "Operands must be two numbers or two strings.",
+                        hint: "Use interpolation like \"${value}\" to combine a string with other values."
+                    ),
                 },
                 Ok(Opcode::Subtract) => binary_op!(-, Number),
                 Ok(Opcode::Multiply) => binary_op!(*, Number),
+                // Like C's fmod, the result takes the sign of the dividend
+                Ok(Opcode::Modulo) => binary_op!(%, Number),
+                Ok(Opcode::Power) => match (pop!(), pop!()) {
+                    (Value::Number(exponent), Value::Number(base)) => {
+                        push!(Value::Number(base.powf(exponent)))
+                    }
+                    _ => runtime_err!("Operands must be numbers."),
+                },
+                Ok(Opcode::FloorDivide) => match (pop!(), pop!()) {
+                    (Value::Number(b), Value::Number(a)) => push!(Value::Number((a / b).floor())),
+                    _ => runtime_err!("Operands must be numbers."),
+                },
                 Ok(Opcode::Divide) => binary_op!(/, Number),
-                Err(code) => {
-                    println!("Invalid opcode {code}");
-                    return Err(InterpretError::CompileError);
-                }
+                Ok(Opcode::BitAnd) => integer_op!(|a, b| Ok(a & b)),
+                Ok(Opcode::BitOr) => integer_op!(|a, b| Ok(a | b)),
+                Ok(Opcode::BitXor) => integer_op!(|a, b| Ok(a ^ b)),
+                Ok(Opcode::ShiftLeft) => integer_op!(|a: i64, b| shift_amount(b).map(|b| a << b)),
+                Ok(Opcode::ShiftRight) => integer_op!(|a: i64, b| shift_amount(b).map(|b| a >> b)),
+                Ok(Opcode::BitNot) => match as_integer(&pop!()) {
+                    Some(val) => push!(Value::Number(!val as f64)),
+                    None => runtime_err!("Operand must be an integer."),
+                },
+                Err(code) => runtime_err!(&format!("Invalid opcode {code}.")),
             }
         }
     }
+    // Frees heap objects that are no longer reachable, and cleans up the string intern map
     pub fn garbage_collect(&mut self) {
+        let VM {
+            heap,
+            values,
+            globals,
+            frames,
+            open_upvalues,
+            debug_output,
+            host_roots,
+            ..
+        } = self;
+        heap.collect(&mut **debug_output, |tracer| {
+            for value in values.iter() {
+                tracer.mark_value(value);
+            }
+            for value in globals.values() {
+                tracer.mark_value(value);
+            }
+            for frame in frames.iter() {
+                tracer.mark(frame.closure);
+            }
+            for upvalue in open_upvalues.iter() {
+                tracer.mark(*upvalue);
+            }
+            host_roots.mark(tracer);
+        });
         self.strings.clean();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_unreachable_cycles() {
+        let mut vm = VM::new();
+        vm.interpret(
+            "
+            class A {}
+            {
+                var a = A();
+                var b = A();
+                a.other = b;
+                b.other = a;
+            }"
+            .to_string(),
+        )
+        .unwrap();
+        vm.garbage_collect();
+        // Only the class is still reachable, from the globals
+        assert_eq!(vm.heap.object_count(), 1);
+    }
+
+    #[test]
+    fn stack_limit_is_a_runtime_error() {
+        let mut vm = VM::new();
+        vm.set_stack_limit(8);
+        let result = vm.interpret(
+            "
+            {
+                var a = 1; var b = 2; var c = 3; var d = 4;
+                var e = 5; var f = 6; var g = 7; var h = 8;
+            }"
+            .to_string(),
+        );
+        assert!(matches!(result, Err(InterpretError::RuntimeError(_))));
+        // The stack is reset after the error, so the VM can keep going
+        vm.interpret("var a = 1;".to_string()).unwrap();
+    }
+
+    #[test]
+    fn deep_recursion_within_the_stack_limit() {
+        let sum = "
+            fun sum(n) { if (n == 0) return 0; return n + sum(n - 1); }
+            if (sum(10000) != 50005000) fail;";
+        let mut vm = VM::new();
+        let Err(InterpretError::RuntimeError(err)) = vm.interpret(sum.to_string()) else {
+            panic!("should overflow the default stack")
+        };
+        assert_eq!(err.diagnostic.message, "Stack overflow.");
+
+        vm.set_stack_limit(100_000);
+        vm.interpret(sum.to_string()).unwrap();
+    }
+
+    #[test]
+    fn escaped_closures_survive_a_runtime_error() {
+        let mut vm = VM::new();
+        let result = vm.interpret(
+            "var f;
+            {
+                var a; var b; var c; var x = 1;
+                fun g() { return x; }
+                f = g;
+                nil();
+            }"
+            .to_string(),
+        );
+        assert!(matches!(result, Err(InterpretError::RuntimeError(_))));
+        // The upvalue was closed when the stack was reset, rather than left pointing at a slot that's gone
+        vm.interpret("if (f() != 1) fail;".to_string()).unwrap();
+    }
+
+    #[test]
+    fn stack_grows_past_initial_capacity() {
+        let mut vm = VM::new();
+        let locals: String = (0..255).map(|i| format!("var v{i} = {i};")).collect();
+        vm.interpret(format!("fun f() {{ {locals} }} f(); f();"))
+            .unwrap();
+    }
+
+    #[test]
+    fn more_than_256_constants() {
+        let mut vm = VM::new();
+        // Each global takes a constant for its value, and a slot past the first 256 for the later ones
+        let globals: String = (0..300).map(|i| format!("var g{i} = {i};")).collect();
+        vm.interpret(format!(
+            "{globals}
+            g299 = g299 + 1;
+            if (g299 != 300 or g0 != 0) fail;"
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn instructions_after_256_constants() {
+        let mut vm = VM::new();
+        let constants: String = (0..300).map(|i| format!("{i};")).collect();
+        vm.interpret(format!(
+            "{constants}
+            fun add(a) {{
+                fun inner(b) {{ return a + b; }}
+                return inner;
+            }}
+            class Base {{
+                value() {{ return this.field; }}
+            }}
+            class Derived < Base {{
+                value() {{ return super.value() + 1; }}
+            }}
+            var derived = Derived();
+            derived.field = add(1)(2);
+            if (derived.value() != 4) fail;"
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn globals_keep_their_slots_across_interprets() {
+        let mut vm = VM::new();
+        vm.interpret("var a = 1; fun getB() { return b; }".to_string())
+            .unwrap();
+        // `b` was given a slot when getB was compiled, defining it later fills that slot in
+        vm.interpret("var b = a + 1; if (getB() != 2) fail;".to_string())
+            .unwrap();
+    }
+
+    #[test]
+    fn bitwise_operators_need_integers() {
+        for source in [
+            "1.5 & 1;",
+            "1 << 64;",
+            "1 >> -1;",
+            "~\"a\";",
+            "10000000000000000000 | 1;",
+        ] {
+            let result = VM::new().interpret(source.to_string());
+            assert!(
+                matches!(result, Err(InterpretError::RuntimeError(_))),
+                "{source} should fail"
+            );
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use std::cell::RefCell;
+
+use super::VM;
+use crate::{
+    heap::Heap,
+    value::{List, Map, MapKey, Value},
+};
+
+// Built-in native functions, available in every VM
+impl VM {
+    pub(super) fn define_builtin_natives(&mut self) {
+        self.define_builtin_native("clock", 0, clock);
+        self.define_builtin_native("len", 1, len);
+        self.define_heap_native("push", 2, push);
+        self.define_builtin_native("pop", 1, pop);
+        self.define_heap_native("keys", 1, keys);
+        self.define_heap_native("values", 1, values);
+        self.define_builtin_native("has", 2, has);
+        self.define_builtin_native("remove", 2, remove);
+    }
+}
+
+// Seconds since the unix epoch - mostly useful for timing things by subtracting two calls
+fn clock(_: &[Value]) -> Result<Value, String> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| Value::Number(time.as_secs_f64()))
+        .map_err(|_| "System clock is set before the unix epoch.".to_string())
+}
+
+fn len(args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::List(list) => Ok(Value::Number(list.borrow().items.len() as f64)),
+        Value::Map(map) => Ok(Value::Number(map.borrow().entries.len() as f64)),
+        Value::String(s) => Ok(Value::Number(s.chars().count() as f64)),
+        _ => Err("Can only get the length of lists, maps and strings.".to_string()),
+    }
+}
+
+// Adds the value to the end of the list
+fn push(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    let Value::List(list) = &args[0] else {
+        return Err("Can only push to lists.".to_string());
+    };
+    heap.grow(*list, |list| list.borrow_mut().items.push(args[1].clone()));
+    Ok(Value::Nil)
+}
+
+// Removes and returns the last value in the list
+fn pop(args: &[Value]) -> Result<Value, String> {
+    let Value::List(list) = &args[0] else {
+        return Err("Can only pop from lists.".to_string());
+    };
+    list.borrow_mut()
+        .items
+        .pop()
+        .ok_or_else(|| "Can't pop from an empty list.".to_string())
+}
+
+fn expect_map(value: &Value) -> Result<&RefCell<Map>, String> {
+    match value {
+        Value::Map(map) => Ok(map),
+        _ => Err("Expected a map.".to_string()),
+    }
+}
+
+// The map's keys as a list, in sorted order
+fn keys(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    let map = expect_map(&args[0])?.borrow();
+    let keys = map
+        .sorted_entries()
+        .into_iter()
+        .map(|(key, _)| key.to_value())
+        .collect();
+    Ok(Value::List(heap.alloc(RefCell::new(List::new(keys)))))
+}
+
+// The map's values as a list, in the same order as `keys`
+fn values(heap: &mut Heap, args: &[Value]) -> Result<Value, String> {
+    let map = expect_map(&args[0])?.borrow();
+    let values = map
+        .sorted_entries()
+        .into_iter()
+        .map(|(_, value)| value.clone())
+        .collect();
+    Ok(Value::List(heap.alloc(RefCell::new(List::new(values)))))
+}
+
+fn has(args: &[Value]) -> Result<Value, String> {
+    let map = expect_map(&args[0])?.borrow();
+    let key = MapKey::from_value(&args[1])?;
+    Ok(Value::Bool(map.entries.contains_key(&key)))
+}
+
+// Removes the key from the map, returning the value it had, or nil if it wasn't there
+fn remove(args: &[Value]) -> Result<Value, String> {
+    let mut map = expect_map(&args[0])?.borrow_mut();
+    let key = MapKey::from_value(&args[1])?;
+    Ok(map.entries.remove(&key).unwrap_or(Value::Nil))
+}
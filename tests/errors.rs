@@ -0,0 +1,117 @@
+use rlox::{
+    diagnostic::{DiagnosticFormat, DiagnosticKind},
+    vm::{InterpretError, VM},
+};
+
+fn interpret(source: &str) -> InterpretError {
+    VM::new()
+        .interpret(source.to_string())
+        .expect_err("should fail")
+}
+
+#[test]
+fn compile_errors_are_collected() {
+    let InterpretError::CompileError(diagnostics) = interpret("print 1 2;\nvar = 3;\nprint \"ok\"")
+    else {
+        panic!("expected a compile error");
+    };
+    let found: Vec<_> = diagnostics
+        .iter()
+        .map(|d| {
+            (
+                d.kind,
+                d.message.as_str(),
+                d.line,
+                d.column(),
+                d.lexeme.as_deref(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        found,
+        [
+            (
+                DiagnosticKind::CompileError,
+                "Expect ';' after value.",
+                1,
+                Some(9),
+                Some("2")
+            ),
+            (
+                DiagnosticKind::CompileError,
+                "Expect variable name.",
+                2,
+                Some(5),
+                Some("=")
+            ),
+            (
+                DiagnosticKind::CompileError,
+                "Expect ';' after value.",
+                3,
+                Some(11),
+                Some("")
+            ),
+        ]
+    );
+    assert_eq!(
+        diagnostics[2].render(DiagnosticFormat::Plain),
+        "[line 3] Error at end: Expect ';' after value."
+    );
+}
+
+#[test]
+fn scan_errors_have_no_lexeme() {
+    let InterpretError::CompileError(diagnostics) = interpret("var a = 1;\nvar b = #;") else {
+        panic!("expected a compile error");
+    };
+    assert_eq!(diagnostics.len(), 1);
+    let diagnostic = &diagnostics[0];
+    assert_eq!(diagnostic.message, "Unexpected character.");
+    assert_eq!((diagnostic.line, diagnostic.column()), (2, Some(9)));
+    assert_eq!(diagnostic.lexeme, None);
+    assert_eq!(diagnostic.source_line.as_deref(), Some("var b = #;"));
+}
+
+#[test]
+fn runtime_errors_have_a_stack_trace() {
+    let source = "fun inner() {\n  return nil + 1;\n}\nfun outer() { inner(); }\nouter();";
+    let InterpretError::RuntimeError(err) = interpret(source) else {
+        panic!("expected a runtime error");
+    };
+    assert_eq!(err.diagnostic.kind, DiagnosticKind::RuntimeError);
+    assert_eq!(
+        err.diagnostic.message,
+        "Operands must be two numbers or two strings."
+    );
+    assert_eq!(
+        (err.diagnostic.line, err.diagnostic.column()),
+        (2, Some(14))
+    );
+    let trace: Vec<_> = err
+        .stack_trace
+        .iter()
+        .map(|frame| (frame.function.as_deref(), frame.line))
+        .collect();
+    assert_eq!(trace, [(Some("inner"), 2), (Some("outer"), 4), (None, 5)]);
+    assert_eq!(
+        err.render(DiagnosticFormat::Plain),
+        "Operands must be two numbers or two strings.\n\
+         [line 2] in inner()\n\
+         [line 4] in outer()\n\
+         [line 5] in script"
+    );
+}
+
+#[test]
+fn runtime_errors_point_into_earlier_sources() {
+    // The function comes from an earlier interpret, like a previous REPL line
+    let mut vm = VM::new();
+    vm.interpret("fun f(x) {\n  return -x;\n}".to_string())
+        .unwrap();
+    let Err(InterpretError::RuntimeError(err)) = vm.interpret("f(\"a\");".to_string()) else {
+        panic!("expected a runtime error");
+    };
+    assert_eq!(err.diagnostic.message, "Operand must be a number.");
+    assert_eq!(err.diagnostic.source_line.as_deref(), Some("  return -x;"));
+    assert_eq!(err.diagnostic.column(), Some(10));
+}
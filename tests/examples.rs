@@ -20,7 +20,7 @@ struct TestCase {
 
 fn run_rlox(file: String) -> Result<Output, std::io::Error> {
     let mut input = Command::new("./target/debug/rlox");
-    input.arg(file).output()
+    input.arg("--plain").arg(file).output()
 }
 
 fn run_test(case: TestCase) {
@@ -57,6 +57,15 @@ fn unicode_comments_test() {
     });
 }
 
+#[test]
+fn identifiers() {
+    run_test(TestCase {
+        file: "identifiers",
+        stdout: "6\ncoffee\nvariable\nomega\nfteaclassy\nnot a for loop\n42\n",
+        result: Success,
+    });
+}
+
 #[test]
 fn expressions() {
     run_test(TestCase {
@@ -66,6 +75,51 @@ fn expressions() {
     });
 }
 
+#[test]
+fn arithmetic_operators() {
+    run_test(TestCase {
+        file: "arithmetic_operators",
+        stdout: "1\n-1\n1\n1.5\n1024\n512\n-4\n0.5\n18\n3\n-4\n3\n7\n1\n",
+        result: Failure(
+            RuntimeError,
+            "Operands must be numbers.\n[line 16] in script\n",
+        ),
+    });
+}
+
+#[test]
+fn bitwise_operators() {
+    run_test(TestCase {
+        file: "bitwise_operators",
+        stdout: "8\n14\n6\n-6\n16\n-4\n3\n2\n6\ntrue\ntrue\n",
+        result: Failure(
+            RuntimeError,
+            "Operands must be integers.\n[line 15] in script\n",
+        ),
+    });
+}
+
+#[test]
+fn number_literals() {
+    run_test(TestCase {
+        file: "number_literals",
+        stdout: "255\n10\n15\n0.0015\n2000\n1000000\n240\n12.5\n",
+        result: Success,
+    });
+}
+
+#[test]
+fn number_literal_errors() {
+    run_test(TestCase {
+        file: "number_literal_errors",
+        stdout: "",
+        result: Failure(
+            CompileError,
+            "[line 2] Error: Malformed number literal.\n[line 3] Error: Digit separators must be between digits.\n[line 4] Error: Expect digits in hex literal.\n",
+        ),
+    });
+}
+
 #[test]
 fn plus_operator() {
     run_test(TestCase {
@@ -135,10 +189,283 @@ fn logical() {
 fn control_flow() {
     run_test(TestCase {
         file: "control_flow",
-        stdout: "false\nalways\n0\n1\n2\n0\n1\n0\n1\n1\n2\n",
+        stdout: "false\nalways\n0\n1\n2\n0\n1\n0\n1\n1\n2\n0\n1\n10\n11\n0\n1\n10\n",
+        result: Failure(
+            RuntimeError,
+            "Undefined variable 'g'.\n[line 62] in script\n",
+        ),
+    });
+}
+
+#[test]
+fn break_continue() {
+    run_test(TestCase {
+        file: "break_continue",
+        stdout: "1\n3\n0\n4\n0\n10\n1\n3\ndone\n",
+        result: Success,
+    });
+}
+
+#[test]
+fn loop_errors() {
+    run_test(TestCase {
+        file: "loop_errors",
+        stdout: "",
+        result: Failure(
+            CompileError,
+            "[line 1] Error at 'break': Can't use 'break' outside of a loop.\n[line 4] Error at 'continue': Can't use 'continue' outside of a loop.\n",
+        ),
+    });
+}
+
+#[test]
+fn string_escapes() {
+    run_test(TestCase {
+        file: "string_escapes",
+        stdout: "tab\there\ntwo\nlines\nsay \"hi\"\nback\\slash\nHéllo 😀\ntrue\n",
+        result: Success,
+    });
+}
+
+#[test]
+fn string_escape_errors() {
+    run_test(TestCase {
+        file: "string_escape_errors",
+        stdout: "",
+        result: Failure(
+            CompileError,
+            "[line 3] Error: Unknown escape sequence '\\q'.\n[line 4] Error: Invalid unicode escape sequence.\n",
+        ),
+    });
+}
+
+#[test]
+fn string_interpolation() {
+    run_test(TestCase {
+        file: "string_interpolation",
+        stdout: "Hello Ada, you are 36\n3\nnil is nil, true is true, half is 0.5\nnested inner Ada string\nhi you!\na Point and Point instance\nbraces { } and a ${literal}\ntrue\n",
+        result: Success,
+    });
+}
+
+#[test]
+fn interpolation_errors() {
+    run_test(TestCase {
+        file: "interpolation_errors",
+        stdout: "",
+        result: Failure(
+            CompileError,
+            "[line 2] Error at 'b': Expect '}' after interpolated expression.\n",
+        ),
+    });
+}
+
+#[test]
+fn lists() {
+    run_test(TestCase {
+        file: "lists",
+        stdout: "[1, 2, 3]\n4\n[1, two, 3]\n5\n[]\n3\n4\n4\n[1, 5, 3]\n[[1, 2], [20, 4]]\n7\n5\ntrue\nfalse\n[1, 5, 3, [...]]\n[0, 1, 4, 9, 16]\n",
+        result: Failure(RuntimeError, "List index out of range.\n[line 40] in script\n"),
+    });
+}
+
+#[test]
+fn list_errors() {
+    run_test(TestCase {
+        file: "list_errors",
+        stdout: "1\n",
+        result: Failure(
+            RuntimeError,
+            "Can't pop from an empty list.\n[line 2] in last()\n[line 5] in script\n",
+        ),
+    });
+}
+
+#[test]
+fn maps() {
+    run_test(TestCase {
+        file: "maps",
+        stdout: "{a: 1, b: 2}\n3\n3\n3\none\nyes\nnothing\nstring one\n{nil: nothing, true: yes, 1: one, 1: string one}\n{b: 1}\n{bc: 1}\n{d: 2, key: 3}\n[a, b, c]\n[1, 2, 3]\ntrue\nfalse\n1\nnil\n{b: 2, c: 3}\n2\n{}\n{a: 1}\n{b: 2, c: 3, self: {...}}\n",
+        result: Failure(RuntimeError, "Undefined key 'missing'.\n[line 40] in script\n"),
+    });
+}
+
+#[test]
+fn map_errors() {
+    run_test(TestCase {
+        file: "map_errors",
+        stdout: "false\n",
+        result: Failure(
+            RuntimeError,
+            "Map key must be a string, number, bool or nil.\n[line 3] in script\n",
+        ),
+    });
+}
+
+#[test]
+fn functions() {
+    run_test(TestCase {
+        file: "functions",
+        stdout: "3\n<fn add>\n55\nnil\n15\nearly\nlate\n",
         result: Failure(
             RuntimeError,
-            "Undefined variable 'g'.\n[line 31] in script\n",
+            "\
+Operands must be two numbers or two strings.
+[line 36] in broken()
+[line 39] in caller()
+[line 41] in script\n",
         ),
     });
 }
+
+#[test]
+fn function_errors() {
+    run_test(TestCase {
+        file: "function_errors",
+        stdout: "",
+        result: Failure(
+            CompileError,
+            "[line 1] Error at 'return': Can't return from top-level code.\n",
+        ),
+    });
+}
+
+#[test]
+fn call_errors() {
+    run_test(TestCase {
+        file: "call_errors",
+        stdout: "1\n",
+        result: Failure(
+            RuntimeError,
+            "Expected 2 arguments but got 1.\n[line 5] in script\n",
+        ),
+    });
+}
+
+#[test]
+fn stack_overflow() {
+    let output = run_rlox("./tests/examples/stack_overflow.lox".to_string()).unwrap();
+    assert_eq!(output.status.code().unwrap(), 70);
+    assert!(str::from_utf8(&output.stderr)
+        .unwrap()
+        .starts_with("Stack overflow.\n[line 2] in recurse()\n"));
+}
+
+#[test]
+fn closures() {
+    run_test(TestCase {
+        file: "closures",
+        stdout: "1\n2\n1\n<fn increment>\nafter\nouter\n0\n1\n",
+        result: Success,
+    });
+}
+
+#[test]
+fn classes() {
+    run_test(TestCase {
+        file: "classes",
+        stdout: "Point\nPoint instance\n3\n6\n14\n<fn sum>\nfield\n2\ntrue\n",
+        result: Failure(
+            RuntimeError,
+            "Undefined property 'missing'.\n[line 53] in script\n",
+        ),
+    });
+}
+
+#[test]
+fn class_errors() {
+    run_test(TestCase {
+        file: "class_errors",
+        stdout: "",
+        result: Failure(
+            CompileError,
+            "\
+[line 1] Error at 'this': Can't use 'this' outside of a class.
+[line 4] Error at 'return': Can't return a value from an initializer.
+[line 8] Error at 'this': Can't use 'this' outside of a class.\n",
+        ),
+    });
+}
+
+#[test]
+fn inheritance() {
+    run_test(TestCase {
+        file: "inheritance",
+        stdout: "\
+Rex makes a sound, specifically a bark
+I am Rex
+Rex jr makes a sound, specifically a bark!\n",
+        result: Failure(
+            RuntimeError,
+            "Superclass must be a class.\n[line 36] in script\n",
+        ),
+    });
+}
+
+#[test]
+fn inheritance_errors() {
+    run_test(TestCase {
+        file: "inheritance_errors",
+        stdout: "",
+        result: Failure(
+            CompileError,
+            "\
+[line 1] Error at 'A': A class can't inherit from itself.
+[line 4] Error at 'super': Can't use 'super' in a class with no superclass.
+[line 7] Error at 'super': Can't use 'super' outside of a class.\n",
+        ),
+    });
+}
+
+#[test]
+fn natives() {
+    run_test(TestCase {
+        file: "natives",
+        stdout: "<native fn>\ntrue\n",
+        result: Failure(
+            RuntimeError,
+            "Expected 0 arguments but got 1.\n[line 3] in script\n",
+        ),
+    });
+}
+
+#[test]
+fn garbage_collection() {
+    run_test(TestCase {
+        file: "garbage_collection",
+        stdout: "20000\nkept\n",
+        result: Success,
+    });
+}
+
+#[test]
+fn local_slots() {
+    run_test(TestCase {
+        file: "local_slots",
+        stdout: "7\n14\n-4\n3\n30\n30\n11\n2\n9\n",
+        result: Success,
+    });
+}
+
+#[test]
+fn rich_diagnostics() {
+    // Without --plain the error shows the source line, and stderr isn't a terminal here so there's no colour
+    let output = Command::new("./target/debug/rlox")
+        .arg("./tests/examples/rich_diagnostics.lox")
+        .output()
+        .unwrap();
+    assert_eq!(str::from_utf8(&output.stdout).unwrap(), "Hello you\n");
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(
+        str::from_utf8(&output.stderr).unwrap(),
+        "runtime error: Operands must be two numbers or two strings.
+ --> line 2, column 19
+  |
+2 |   return \"Hello \" + name;
+  |                   ^
+  = hint: Use interpolation like \"${value}\" to combine a string with other values.
+[line 2] in greet()
+[line 5] in script
+"
+    );
+}
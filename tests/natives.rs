@@ -0,0 +1,139 @@
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use rlox::{
+    vm::{InterpretError, VM},
+    Value,
+};
+
+fn add(args: &[Value]) -> Result<Value, String> {
+    match args {
+        [Value::Number(a), Value::Number(b)] => Ok(Value::Number(a + b)),
+        _ => Err("Arguments to add must be numbers.".to_string()),
+    }
+}
+
+fn greeting(_: &[Value]) -> Result<Value, String> {
+    Ok(Value::from("hello"))
+}
+
+// Scripts signal a failed check by reading an undefined variable, which is a runtime error
+fn run(source: &str) -> Result<(), InterpretError> {
+    let mut vm = VM::new();
+    vm.define_native("add", 2, add);
+    vm.define_native("greeting", 0, greeting);
+    vm.interpret(source.to_string())
+}
+
+#[test]
+fn calls_host_function() {
+    run("if (add(1, 2) != 3) fail;").unwrap();
+}
+
+#[test]
+fn native_strings_are_interned() {
+    run("if (greeting() != \"hello\") fail;").unwrap();
+}
+
+#[test]
+fn native_errors_are_runtime_errors() {
+    match run("add(1, \"2\");") {
+        Err(InterpretError::RuntimeError(err)) => {
+            assert_eq!(err.diagnostic.message, "Arguments to add must be numbers.")
+        }
+        other => panic!("expected a runtime error, got {other:?}"),
+    }
+}
+
+#[test]
+fn checks_native_arity() {
+    assert!(matches!(
+        run("add(1);"),
+        Err(InterpretError::RuntimeError(_))
+    ));
+}
+
+#[test]
+fn host_functions_keep_state() {
+    let mut vm = VM::new();
+    let count = Cell::new(0.0);
+    vm.define_native("next", 0, move |_| {
+        count.set(count.get() + 1.0);
+        Ok(Value::Number(count.get()))
+    });
+    vm.interpret("next(); if (next() != 2) fail;".to_string())
+        .unwrap();
+}
+
+#[test]
+fn host_strings_compare_by_contents() {
+    assert_eq!(Value::from("a"), Value::from("a"));
+    assert_ne!(Value::from("a"), Value::from("b"));
+    let mut vm = VM::new();
+    vm.define_native("is_a", 1, |args| {
+        Ok(Value::Bool(args[0] == Value::from("a")))
+    });
+    vm.interpret("if (!is_a(\"a\") or is_a(\"b\")) fail;".to_string())
+        .unwrap();
+}
+
+#[test]
+fn clock_is_builtin() {
+    run("var start = clock(); if (clock() < start) fail;").unwrap();
+}
+
+// A host function that holds on to its argument after the call returns, and one to hand it back
+fn kept_vm() -> (VM, Rc<RefCell<Option<Value>>>) {
+    let kept = Rc::new(RefCell::new(None));
+    let mut vm = VM::new();
+    let keep = kept.clone();
+    vm.define_native("keep", 1, move |args| {
+        *keep.borrow_mut() = Some(args[0].clone());
+        Ok(Value::Nil)
+    });
+    let give_back = kept.clone();
+    vm.define_native("kept", 0, move |_| {
+        Ok(give_back.borrow().clone().unwrap_or(Value::Nil))
+    });
+    (vm, kept)
+}
+
+#[test]
+fn kept_objects_survive_collection() {
+    let (mut vm, kept) = kept_vm();
+    vm.interpret("keep([1, 2, 3]);".to_string()).unwrap();
+    vm.garbage_collect();
+    // Reuses the memory of anything the collection freed
+    vm.interpret("var xs = []; for (var i = 0; i < 100; i = i + 1) push(xs, i);".to_string())
+        .unwrap();
+    let held = kept.borrow().clone().unwrap();
+    assert_eq!(held.to_string(), "[1, 2, 3]");
+    // And it can be handed back to the script
+    vm.interpret("var ys = kept(); push(ys, 4); if (len(ys) != 4) fail;".to_string())
+        .unwrap();
+    assert_eq!(held.to_string(), "[1, 2, 3, 4]");
+
+    drop(vm);
+    let Value::Object(object) = held else {
+        panic!("lists are objects");
+    };
+    assert!(!object.is_alive());
+    assert_eq!(object.to_string(), "<released object>");
+}
+
+#[test]
+fn objects_belong_to_their_vm() {
+    let (mut first, kept) = kept_vm();
+    first.interpret("keep([]);".to_string()).unwrap();
+    let mut second = VM::new();
+    second.define_native("foreign", 0, move |_| Ok(kept.borrow().clone().unwrap()));
+    match second.interpret("foreign();".to_string()) {
+        Err(InterpretError::RuntimeError(err)) => assert_eq!(
+            err.diagnostic.message,
+            "Can't use an object from a different VM."
+        ),
+        other => panic!("expected a runtime error, got {other:?}"),
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use rlox::vm::{InterpretError, VM};
+
+// Keeps a handle on what the VM writes, since the VM owns the writer it's given
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct FailingWriter;
+impl Write for FailingWriter {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(io::Error::other("closed"))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn print_goes_to_the_output() {
+    let output = SharedBuffer::default();
+    let mut vm = VM::new();
+    vm.set_output(output.clone());
+    vm.interpret("print 1 + 2; print \"a${[1, 2]}\";".to_string())
+        .unwrap();
+    vm.interpret("print nil;".to_string()).unwrap();
+    assert_eq!(output.contents(), "3\na[1, 2]\nnil\n");
+}
+
+#[test]
+fn debug_output_is_separate() {
+    let (output, debug_output) = (SharedBuffer::default(), SharedBuffer::default());
+    let mut vm = VM::new();
+    vm.set_output(output.clone());
+    vm.set_debug_output(debug_output.clone());
+    vm.interpret("print \"hi\";".to_string()).unwrap();
+    assert_eq!(output.contents(), "hi\n");
+    // Only the DEBUG_ features write anything here
+    if !cfg!(any(
+        feature = "DEBUG_TRACE_EXECUTION",
+        feature = "DEBUG_PRINT_CODE",
+        feature = "DEBUG_LOG_GC"
+    )) {
+        assert_eq!(debug_output.contents(), "");
+    }
+}
+
+#[test]
+fn failed_writes_are_runtime_errors() {
+    let mut vm = VM::new();
+    vm.set_output(FailingWriter);
+    match vm.interpret("print 1;".to_string()) {
+        Err(InterpretError::RuntimeError(err)) => {
+            assert_eq!(err.diagnostic.message, "Could not write output.")
+        }
+        other => panic!("expected a runtime error, got {other:?}"),
+    }
+}